@@ -1,40 +1,153 @@
-#![feature(exit_status_error)]
-
-use std::process::Command;
+use std::collections::hash_map::DefaultHasher;
 use std::env;
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     ////////////////////////////////////////
     // git-shell
+    // only a fallback, git-shell can find git at runtime too
+    println!("cargo:rerun-if-env-changed=GIT_EXECUTABLE");
     let git_executable = env::var("GIT_EXECUTABLE")
-        .unwrap_or_else(|_| {
+        .ok()
+        .or_else(|| {
             let out = Command::new("which").arg("git")
                 .output()
-                .expect("unable to run `which git`");
+                .ok()?;
 
-            let stdout = String::from_utf8(out.stdout)
-                .expect("stdout is invalid utf-8");
+            let stdout = String::from_utf8_lossy(&out.stdout);
             let stdout = stdout.trim();
 
-            if !out.status.success() {
-                panic!("which: {}", stdout);
+            if !out.status.success() || stdout.is_empty() {
+                println!("cargo:warning=git not found, git-shell will have to be configured at runtime");
+                return None;
             }
 
-            stdout.to_owned()
+            Some(stdout.to_owned())
         });
-    println!("cargo:rustc-env=GIT_EXECUTABLE={}", git_executable);
+    if let Some(git_executable) = git_executable {
+        println!("cargo:rustc-env=GIT_EXECUTABLE={}", git_executable);
+    }
 
     ////////////////////////////////////////
     // git-site
-    Command::new("sassc")
-        .arg("templates/style.scss")
-        .arg("static/style.css")
-        .spawn()
-        .expect("unable to run `sassc`")
-        .wait()
+    compile_stylesheet(Path::new("templates/style.scss"), Path::new("static/style.css"));
+
+    ////////////////////////////////////////
+    // git-site embedded assets
+    if env::var_os("CARGO_FEATURE_EMBED").is_some() {
+        embed_assets();
+    }
+}
+
+/// Compile the SCSS `source` into `target`
+///
+/// git-shell doesn't need any of the site's assets so a missing `source` is only a warning. When
+/// the compilation fails a previously compiled `target` is kept as a fallback.
+fn compile_stylesheet(source: &Path, target: &Path) {
+    println!("cargo:rerun-if-changed={}", source.display());
+    if !source.exists() {
+        println!("cargo:warning={} is missing, git-site won't have a stylesheet", source.display());
+        return;
+    }
+
+    let res = grass::from_path(&source.to_string_lossy(), &grass::Options::default());
+    match res {
+        Ok(css) => {
+            if let Some(dir) = target.parent() {
+                fs::create_dir_all(dir)
+                    .unwrap_or_else(|err| panic!("creating {:?}: {}", dir, err));
+            }
+            fs::write(target, css)
+                .unwrap_or_else(|err| panic!("writing {:?}: {}", target, err));
+        }
+        Err(err) if target.exists() => {
+            println!("cargo:warning=compiling {}: {}, keeping the existing {}", source.display(), err, target.display());
+        }
+        Err(err) => panic!("compiling {}: {}", source.display(), err),
+    }
+}
+
+/// Write `$OUT_DIR/assets.rs` including the templates and static files into the binary
+fn embed_assets() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut out = String::new();
+
+    let templates_dir = root.join("templates");
+    println!("cargo:rerun-if-changed={}", templates_dir.display());
+    out.push_str("pub static TEMPLATES: &[(&str, &str)] = &[\n");
+    for path in files(&templates_dir) {
+        let relative = relative(&templates_dir, &path);
+        let name = match relative.strip_suffix(".tera") {
+            Some(name) => name,
+            None => continue,
+        };
+        // templates are named like `rocket_dyn_templates` names them, without the format extension
+        let name = match name.rsplit_once('.') {
+            Some((stem, ext)) if !ext.contains('/') => stem,
+            _ => name,
+        };
+        out.push_str(&format!("    ({:?}, include_str!({:?})),\n", name, path));
+    }
+    out.push_str("];\n\n");
+
+    let static_dir = root.join("static");
+    out.push_str("pub static STATIC_FILES: &[StaticFile] = &[\n");
+    for path in files(&static_dir) {
+        let relative = relative(&static_dir, &path);
+        let content = fs::read(&path)
+            .unwrap_or_else(|err| panic!("reading {:?}: {}", path, err));
+        // `style.css` is compiled above, watching it would rerun the build script every time
+        if relative != "style.css" {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        out.push_str(&format!(
+            "    StaticFile {{ path: {:?}, hashed: {:?}, content: include_bytes!({:?}) }},\n",
+            relative, hashed_name(&relative, &content), path,
+        ));
+    }
+    out.push_str("];\n");
+
+    fs::write(out_dir.join("assets.rs"), out)
+        .expect("writing assets.rs");
+}
+
+/// All files under `dir` sorted by path
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap_or_else(|err| panic!("reading {:?}: {}", dir, err)) {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+fn relative(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
         .unwrap()
-        .exit_ok()
-        .expect("`sassc` failed");
+        .to_str()
+        .unwrap_or_else(|| panic!("{:?} is not valid UTF-8", path))
+        .to_owned()
+}
+
+/// Insert a hash of `content` before the extension, `style.css` becomes `style.<hash>.css`
+fn hashed_name(path: &str, content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(content);
+    let hash = format!("{:016x}", hasher.finish());
 
-    println!("cargo:rerun-if-changed=templates/style.scss");
+    let (dir, name) = path.rsplit_once('/').map_or(("", path), |(dir, name)| (dir, name));
+    let name = match name.split_once('.') {
+        Some((stem, ext)) => format!("{}.{}.{}", stem, hash, ext),
+        None => format!("{}.{}", name, hash),
+    };
+    if dir.is_empty() { name } else { format!("{}/{}", dir, name) }
 }
@@ -1,19 +1,38 @@
 //! `git-tools/git-shell` is a simplified replacement for `git/git-shell`.
 //!
 //! It can only be called with `-c cmd` option and will give better error messages than the default
-//! git. It however doesn't honor the `GIT_EXEC_PATH` runtime environment variable, instead it uses
-//! hard-coded path provided by the `GIT_EXECUTABLE` at *compile time*.
+//! git. The `git` executable is looked up at runtime, the first of these is used:
+//!
+//! 1. the `GIT_EXECUTABLE` environment variable with the path to `git`
+//! 2. `git_executable` in the config file at `GIT_SHELL_CONFIG` or `/etc/git-tools/git-shell.toml`
+//! 3. the path provided by `GIT_EXECUTABLE` at *compile time*
+//!
+//! A config file which can't be read is only reported as a warning if there is a compile time path
+//! to fall back to.
 
 
 use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
 use std::env;
+use std::fs;
+use std::io;
 use std::os::unix::process::CommandExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 
-/// Absolute path to the `git` executable passed at compile time
-const GIT_EXECUTABLE: &str = env!("GIT_EXECUTABLE");
+/// Absolute path to the `git` executable passed at compile time, the fallback if none is
+/// configured at runtime
+const GIT_EXECUTABLE: Option<&str> = option_env!("GIT_EXECUTABLE");
+
+/// Config file used unless `GIT_SHELL_CONFIG` points elsewhere
+const DEFAULT_CONFIG_PATH: &str = "/etc/git-tools/git-shell.toml";
+
+#[derive(Deserialize)]
+struct Config {
+    /// Absolute path to the `git` executable
+    git_executable: Option<PathBuf>,
+}
 
 /// List of allowed commands and their handler functions
 const ALLOWED_GIT_COMMANDS: &[(&str, fn(&str) -> Result<()>)] = &[
@@ -24,12 +43,52 @@ const ALLOWED_GIT_COMMANDS: &[(&str, fn(&str) -> Result<()>)] = &[
 
 /// Prepare a git `Command` and check the environment
 fn git() -> Result<Command> {
-    let git = Path::new(GIT_EXECUTABLE);
-    ensure!(git.is_absolute(), "GIT_EXECUTABLE={:?} was not an absolute path, please recompile the binary", &git);
-    ensure!(git.exists(), "GIT_EXECUTABLE={:?} does not exist", &git);
+    let (git, source) = git_executable()?;
+    ensure!(git.is_absolute(), "git executable {:?} from {} was not an absolute path", &git, source);
+    ensure!(git.exists(), "git executable {:?} from {} does not exist", &git, source);
     Ok(Command::new(git))
 }
 
+/// Find the path to `git` together with a description of where it came from
+fn git_executable() -> Result<(PathBuf, String)> {
+    if let Some(git) = env::var_os("GIT_EXECUTABLE") {
+        return Ok((PathBuf::from(git), String::from("GIT_EXECUTABLE")));
+    }
+
+    let config_path = env::var_os("GIT_SHELL_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+    let config = match read_config(&config_path) {
+        Ok(config) => config,
+        Err(err) if GIT_EXECUTABLE.is_some() => {
+            eprintln!("warning: {:?}", err);
+            None
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(git) = config.and_then(|config| config.git_executable) {
+        return Ok((git, format!("config file {:?}", config_path)));
+    }
+
+    match GIT_EXECUTABLE {
+        Some(git) => Ok((PathBuf::from(git), String::from("compile time GIT_EXECUTABLE"))),
+        None => bail!("git executable not configured, set `git_executable` in {:?} or the GIT_EXECUTABLE environment variable", config_path),
+    }
+}
+
+/// Read the config file at `path`, `None` if it doesn't exist
+fn read_config(path: &Path) -> Result<Option<Config>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("reading config {:?}", path)),
+    };
+
+    toml::de::from_slice(&data)
+        .with_context(|| format!("parsing config {:?}", path))
+        .map(Some)
+}
+
 /// Execute commands supported by the standard `git-shell`
 fn standard_commands(cmd: &str) -> Result<()> {
     let (cmd, arg) = cmd.split_once(" ")
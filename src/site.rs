@@ -1,7 +1,7 @@
-#![feature(async_closure)]
 #![feature(never_type)]
 
 use rocket::catchers;
+#[cfg(not(feature = "embed"))]
 use rocket_dyn_templates::Template;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -26,29 +26,117 @@ macro_rules! ctx {
 
 
 mod site {
+    pub mod api;
+    pub mod assets;
+    pub mod cache;
+    pub mod commit_search;
     pub mod error;
+    pub mod feed;
+    pub mod find_file;
     pub mod git_repo;
+    pub mod hexdump;
+    pub mod http_cache;
     pub mod http_clone;
     pub mod index;
+    pub mod media;
     pub mod nav;
+    pub mod patch;
+    pub mod render_cache;
     pub mod repo;
+    pub mod repo_name;
     pub mod repo_path;
+    pub mod search;
+    pub mod search_index;
+    pub mod user;
+    pub mod view;
     pub mod web;
 }
 pub use site::*;
 
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub git_root: PathBuf,
+    /// Maximum depth of directories under `git_root` searched for repositories, `1` only finds
+    /// direct children of `git_root`
+    pub repo_depth: usize,
+    /// List repositories without a `site.toml` using the metadata stored in git
+    pub show_unconfigured: bool,
+    /// Static files served under `/static`, with the `embed` feature they override the embedded
+    /// files of the same name
     pub static_dir: PathBuf,
+    /// Public URL of the site, used where absolute links are required (eg. in feeds)
+    pub site_url: String,
+    /// Memory used for caching rendered trees, files and readmes in bytes, also bounds the files in
+    /// `render_cache_dir`. `0` disables the cache
+    pub render_cache_size: usize,
+    /// Fragments larger than this are never cached
+    pub render_cache_entry_size: usize,
+    /// Directory where rendered fragments are additionally stored, they are kept only in memory
+    /// if unset
+    pub render_cache_dir: Option<PathBuf>,
+    /// Blobs larger than this are never loaded for display, only their size and a download link
+    /// are shown
+    pub max_blob_size: usize,
+    /// Text files are truncated to this many bytes
+    pub max_text_size: usize,
+    /// Bytes of a binary file shown on a single hexdump page
+    pub hexdump_page_size: usize,
+    /// Time after which a repository search stops and returns what it found so far
+    pub search_timeout_ms: u64,
+    /// Bytes of file content a single search may read
+    pub search_max_bytes: usize,
+    /// Matching lines after which a search stops
+    pub search_max_matches: usize,
+    /// Directory of the site-wide search index, the index is disabled if unset
+    pub search_index_dir: Option<PathBuf>,
+    /// Seconds between checks for repositories whose default branch changed
+    pub search_index_interval_secs: u64,
+    /// Prefix of SSH clone URLs served by `git-shell`, the repository name is appended to it, eg.
+    /// `git@example.com:`. SSH URLs aren't shown if unset
+    pub ssh_url_base: Option<String>,
+    /// Prefix of HTTP clone URLs, defaults to `site_url`
+    pub http_url_base: Option<String>,
+    /// Request header with the name of the user authenticated by a reverse proxy, push URLs are
+    /// shown only to users listed in the repository's `access.push`
+    pub user_header: Option<String>,
+    /// Name of the site shown in page titles and the navigation
+    pub site_name: String,
+    /// Text shown at the bottom of every page
+    pub footer: Option<String>,
+    /// URL of an image shown next to the site name, eg. `/static/logo.png`
+    pub logo: Option<String>,
+    /// Directory with templates shadowing the built-in or embedded ones of the same name, eg.
+    /// `fragments/repo_nav.html.tera`
+    pub template_override_dir: Option<PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
             git_root: PathBuf::from("/home/git"),
+            repo_depth: 3,
+            show_unconfigured: false,
             static_dir: PathBuf::from("./static"),
+            site_url: String::from("http://localhost:8000"),
+            render_cache_size: 64 << 20,
+            render_cache_entry_size: 4 << 20,
+            render_cache_dir: None,
+            max_blob_size: 16 << 20,
+            max_text_size: 1 << 20,
+            hexdump_page_size: 64 << 10,
+            search_timeout_ms: 2000,
+            search_max_bytes: 256 << 20,
+            search_max_matches: 1000,
+            search_index_dir: None,
+            search_index_interval_secs: 300,
+            ssh_url_base: None,
+            http_url_base: None,
+            user_header: None,
+            site_name: String::from("git.p2502.net"),
+            footer: None,
+            logo: None,
+            template_override_dir: None,
         }
     }
 }
@@ -68,10 +156,50 @@ fn launch() -> _ {
             #[cfg(not(debug_assertions))] { Profile::new("release") }
         });
 
+    // the template fairing is created before `Config` is extracted so the directories are read here
+    #[cfg(not(feature = "embed"))]
+    let templates = {
+        let template_override_dir = figment.extract_inner::<PathBuf>("template_override_dir").ok();
+        let static_dir = figment.extract_inner::<PathBuf>("static_dir").unwrap_or_default();
+        Template::custom(move |engines| {
+            if let Some(dir) = &template_override_dir {
+                view::load_template_overrides(&mut engines.tera, dir);
+            }
+            assets::register_functions(&mut engines.tera, static_dir.clone());
+        })
+    };
+    #[cfg(feature = "embed")]
+    let templates = AdHoc::try_on_ignite("Embedded templates", |rocket| async {
+        match assets::Templates::new(rocket.state::<Config>().unwrap()) {
+            Ok(templates) => Ok(rocket.manage(templates)),
+            Err(err) => {
+                log::error!("loading embedded templates: {:?}", err);
+                Err(rocket)
+            }
+        }
+    });
+
     rocket::custom(figment)
         .attach(AdHoc::config::<Config>())
-        .attach(Template::fairing())
+        .manage(cache::RepoCache::default())
+        .attach(AdHoc::on_ignite("Render cache", |rocket| async {
+            let render_cache = render_cache::RenderCache::new(rocket.state::<Config>().unwrap());
+            rocket.manage(render_cache)
+        }))
+        .manage(search_index::SearchIndex::default())
+        .attach(search_index::SearchIndex::fairing())
+        .attach(templates)
+        .attach(repo_name::NestedRepoNames)
         .mount("/", http_clone::routes())
         .mount("/", web::routes())
+        .mount("/", hexdump::routes())
+        .mount("/", patch::routes())
+        .mount("/", search::routes())
+        .mount("/", commit_search::routes())
+        .mount("/", find_file::routes())
+        .mount("/", search_index::routes())
+        .mount("/", feed::routes())
+        .mount(api::BASE, api::routes())
         .register("/", catchers![error::default_catcher])
+        .register(api::BASE, catchers![error::api_catcher])
 }
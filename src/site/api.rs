@@ -0,0 +1,248 @@
+//! Versioned JSON API mirroring the HTML views
+//!
+//! All routes are mounted under `/api/v1`, errors are reported by [`crate::error::api_catcher`].
+
+use crate::cache::RepoCache;
+use crate::commit_search;
+use crate::error::Result;
+use crate::find_file;
+use crate::git_repo::{GitRepo, LargeBlob, Object};
+use crate::index::Index;
+use crate::render_cache::RenderCache;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::web;
+use crate::Config;
+use anyhow::Context as _;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, routes, Route, State};
+use serde_json::Value;
+use std::path::Path;
+
+
+/// Default number of commits returned by the log endpoint
+const LOG_DEFAULT_LIMIT: usize = 50;
+
+/// Maximum number of commits returned by the log endpoint
+const LOG_MAX_LIMIT: usize = 500;
+
+pub const BASE: &str = "/api/v1";
+
+
+#[get("/")]
+async fn index(index: Index) -> Json<Value> {
+    let Index { mut repos, .. } = index;
+
+    repos.sort_unstable_by_key(|repo| repo.name.to_ascii_lowercase());
+
+    Json(ctx!{ repos })
+}
+
+#[get("/<_repo_name>", rank = 2)]
+async fn repo(_repo_name: RepoName, repo: Repo) -> Json<Value> {
+    Json(ctx!{ repo })
+}
+
+/// Directory listing for trees or blob metadata without the content
+#[get("/<_repo_name>/tree/<refs>/<path..>", rank = 2)]
+async fn tree(_repo_name: RepoName, refs: &str, path: RepoPath, repo: Repo, git_repo: GitRepo, config: &State<Config>, repo_cache: &State<RepoCache>) -> Result<Option<Json<Value>>> {
+    let submodules = web::Submodules::read(refs, &git_repo);
+    let submodules = submodules.resolve(&repo, config, repo_cache).await;
+
+    let object = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_blob_size))
+        .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))?;
+
+    Ok(object.map(|object| match object {
+        Object::Tree(tree) => Json(ctx!{
+            kind = "tree",
+            files = web::ls_files(&tree, refs, &path, &repo, &git_repo, &submodules),
+        }),
+        Object::Blob(blob) => Json(ctx!{
+            kind = "blob",
+            blob = blob_ctx(&blob, &path, &repo, false),
+        }),
+        Object::LargeBlob(blob) => Json(ctx!{
+            kind = "blob",
+            blob = large_blob_ctx(&blob, &path),
+        }),
+    }))
+}
+
+/// Blob metadata and content, content is `null` for binary blobs and blobs over `max_blob_size`
+#[get("/<_repo_name>/blob/<refs>/<path..>", rank = 2)]
+async fn blob(_repo_name: RepoName, refs: &str, path: RepoPath, repo: Repo, git_repo: GitRepo, config: &State<Config>) -> Result<Option<Json<Value>>> {
+    let object = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_blob_size))
+        .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))?;
+
+    Ok(match object {
+        Some(Object::Blob(blob)) => Some(Json(ctx!{
+            blob = blob_ctx(&blob, &path, &repo, true),
+        })),
+        Some(Object::LargeBlob(blob)) => Some(Json(ctx!{
+            blob = large_blob_ctx(&blob, &path),
+        })),
+        _ => None,
+    })
+}
+
+/// All file paths of `refs`, or only those fuzzy matching `q` with the best matches first
+#[get("/<_repo_name>/files/<refs>?<q>", rank = 2)]
+async fn files(_repo_name: RepoName, refs: &str, q: Option<&str>, git_repo: GitRepo, render_cache: &State<RenderCache>) -> Result<Option<Json<Value>>> {
+    let paths = match find_file::tree_paths(&git_repo, refs, render_cache)? {
+        Some(paths) => paths,
+        None => return Ok(None),
+    };
+    let paths = paths.as_slice();
+
+    Ok(Some(match q {
+        Some(query) => Json(ctx!{ files = find_file::fuzzy_find(paths, query) }),
+        None => Json(ctx!{ paths }),
+    }))
+}
+
+#[get("/<_repo_name>/branches", rank = 2)]
+async fn branches(_repo_name: RepoName, git_repo: GitRepo) -> Result<Json<Value>> {
+    let branches = git_repo.branches()?;
+
+    Ok(Json(ctx!{ branches }))
+}
+
+#[get("/<_repo_name>/tags", rank = 2)]
+async fn tags(_repo_name: RepoName, git_repo: GitRepo) -> Result<Json<Value>> {
+    let tags = git_repo.tags()?;
+
+    Ok(Json(ctx!{ tags }))
+}
+
+#[get("/<_repo_name>/log/<refs>?<skip>&<limit>", rank = 2)]
+async fn commit_log(_repo_name: RepoName, refs: &str, skip: Option<usize>, limit: Option<usize>, git_repo: GitRepo) -> Result<Option<Json<Value>>> {
+    let commit = match git_repo.find_commit(refs)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let skip = skip.unwrap_or(0);
+    let limit = limit.unwrap_or(LOG_DEFAULT_LIMIT).min(LOG_MAX_LIMIT);
+    let commits = git_repo.log(&commit, skip, limit)?;
+
+    Ok(Some(Json(ctx!{
+        commits,
+        skip,
+        limit,
+    })))
+}
+
+/// Commits of `refs` filtered like the HTML commit search, see [`commit_search::filter`]
+///
+/// `complete` is `false` if the search hit the time limit before walking the whole history.
+#[get("/<_repo_name>/commits/<refs>?<author>&<committer>&<message>&<since>&<until>&<path>&<skip>&<limit>", rank = 2)]
+async fn search_commits(
+    _repo_name: RepoName,
+    refs: &str,
+    author: Option<&str>,
+    committer: Option<&str>,
+    message: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    path: Option<&str>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    git_repo: GitRepo,
+    config: &State<Config>,
+) -> Result<Option<(Status, Json<Value>)>> {
+    let commit = match git_repo.find_commit(refs)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let filter = match commit_search::filter(author, committer, message, since, until, path) {
+        Ok(filter) => filter,
+        Err(details) => return Ok(Some((Status::BadRequest, Json(ctx!{
+            error = ctx!{
+                code = Status::BadRequest.code,
+                details,
+                reason = Status::BadRequest.reason(),
+            },
+        })))),
+    };
+
+    let skip = skip.unwrap_or(0);
+    let limit = limit.unwrap_or(LOG_DEFAULT_LIMIT).clamp(1, LOG_MAX_LIMIT);
+    let search = git_repo.search_commits(&commit, &filter, skip, limit, commit_search::deadline(config))?;
+
+    Ok(Some((Status::Ok, Json(ctx!{
+        commits = search.commits,
+        skip,
+        limit,
+        complete = search.complete,
+    }))))
+}
+
+#[get("/<_repo_name>/commit/<id>", rank = 2)]
+async fn commit(_repo_name: RepoName, id: &str, git_repo: GitRepo) -> Result<Option<Json<Value>>> {
+    let commit = match git_repo.find_commit(id)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let commit = git_repo.commit_details(&commit)?;
+
+    Ok(Some(Json(ctx!{ commit })))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        index,
+        repo,
+        tree,
+        blob,
+        files,
+        branches,
+        tags,
+        commit_log,
+        search_commits,
+        commit,
+    }
+}
+
+
+fn blob_ctx(blob: &git2::Blob<'_>, path: &Path, repo: &Repo, with_content: bool) -> Value {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string());
+    let is_binary = blob.is_binary();
+    let lang = match (&name, is_binary) {
+        (Some(name), false) => web::blob_lang(name, repo),
+        _ => None,
+    };
+    let content = (with_content && !is_binary)
+        .then(|| String::from_utf8_lossy(blob.content()).to_string());
+
+    ctx!{
+        id = blob.id().to_string(),
+        name,
+        path,
+        size = blob.size(),
+        is_binary,
+        lang,
+        content,
+    }
+}
+
+/// Metadata of a blob over `max_blob_size`, binary detection needs the content so `is_binary` is
+/// `null`
+fn large_blob_ctx(blob: &LargeBlob, path: &Path) -> Value {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string());
+
+    ctx!{
+        id = blob.id.to_string(),
+        name,
+        path,
+        size = blob.size,
+        is_binary = None::<bool>,
+        lang = None::<String>,
+        content = None::<String>,
+    }
+}
@@ -0,0 +1,133 @@
+//! Templates and static files embedded into the binary by the `embed` feature
+//!
+//! Embedded static files are additionally served under a name containing a hash of their content,
+//! eg. `/static/style.0123456789abcdef.css`, which can be cached forever. Files in `static_dir`
+//! and templates in `template_override_dir` take precedence over the embedded ones.
+
+use crate::http_cache::CACHE_IMMUTABLE;
+#[cfg(feature = "embed")]
+use crate::Config;
+use rocket::fs::NamedFile;
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_dyn_templates::tera::{self, Tera};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+pub struct StaticFile {
+    /// Path relative to the static directory
+    pub path: &'static str,
+    /// `path` with a hash of the content inserted before the extension
+    pub hashed: &'static str,
+    pub content: &'static [u8],
+}
+
+// `TEMPLATES` holds the names and sources of the templates, `STATIC_FILES` the static files
+#[cfg(feature = "embed")]
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+#[cfg(not(feature = "embed"))]
+pub static STATIC_FILES: &[StaticFile] = &[];
+
+/// Static file either read from `static_dir` or embedded
+pub enum Static {
+    File(NamedFile),
+    Embedded {
+        file: &'static StaticFile,
+        /// Requested by the hashed name, the content can never change
+        immutable: bool,
+    },
+}
+
+/// Embedded static file named either by its path or its hashed path
+pub fn find(path: &str) -> Option<Static> {
+    STATIC_FILES.iter()
+        .find_map(|file| if path == file.hashed {
+            Some(Static::Embedded { file, immutable: true })
+        } else if path == file.path {
+            Some(Static::Embedded { file, immutable: false })
+        } else {
+            None
+        })
+}
+
+/// URLs of the embedded static files keyed by their path
+///
+/// Embedded files get the hashed name unless they are overridden in `static_dir`, overrides may
+/// change at any time so they are always linked by their plain name. Overrides are only looked for
+/// once, adding one takes a restart to be linked by its plain name.
+fn urls(static_dir: &Path) -> HashMap<&'static str, String> {
+    STATIC_FILES.iter()
+        .map(|file| {
+            let name = if static_dir.join(file.path).exists() { file.path } else { file.hashed };
+            (file.path, format!("/static/{}", name))
+        })
+        .collect()
+}
+
+/// Register the functions used by the templates
+///
+/// - `asset(path)` returns the URL of a static file, see [`urls`]
+pub fn register_functions(tera: &mut Tera, static_dir: PathBuf) {
+    let urls = urls(&static_dir);
+    tera.register_function("asset", move |args: &HashMap<String, tera::Value>| {
+        let path = args.get("path")
+            .and_then(|path| path.as_str())
+            .ok_or_else(|| tera::Error::msg("`asset` requires a string argument `path`"))?;
+        let url = urls.get(path)
+            .cloned()
+            .unwrap_or_else(|| format!("/static/{}", path));
+        Ok(tera::Value::String(url))
+    });
+}
+
+/// Embedded templates rendered from memory, replaces the `rocket_dyn_templates` fairing with the
+/// `embed` feature since that one only renders templates found in `template_dir`
+#[cfg(feature = "embed")]
+pub struct Templates {
+    tera: Tera,
+}
+
+#[cfg(feature = "embed")]
+impl Templates {
+    pub fn new(config: &Config) -> tera::Result<Templates> {
+        let mut tera = Tera::default();
+        // the names don't carry the format extension, all of the templates are HTML or XML
+        tera.autoescape_on(vec![""]);
+        tera.add_raw_templates(TEMPLATES.to_vec())?;
+        if let Some(dir) = &config.template_override_dir {
+            crate::view::load_template_overrides(&mut tera, dir);
+        }
+        register_functions(&mut tera, config.static_dir.clone());
+
+        Ok(Templates { tera })
+    }
+
+    pub fn render(&self, name: &str, ctx: &serde_json::Value) -> tera::Result<String> {
+        let ctx = tera::Context::from_serialize(ctx)?;
+        self.tera.render(name, &ctx)
+    }
+}
+
+impl<'req> Responder<'req, 'static> for Static {
+    fn respond_to(self, request: &'req Request<'_>) -> response::Result<'static> {
+        let (file, immutable) = match self {
+            Static::File(file) => return file.respond_to(request),
+            Static::Embedded { file, immutable } => (file, immutable),
+        };
+
+        let content_type = Path::new(file.path).extension()
+            .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()))
+            .unwrap_or(ContentType::Binary);
+
+        let mut response = Response::build();
+        response.header(content_type)
+            .sized_body(file.content.len(), Cursor::new(file.content));
+        if immutable {
+            response.raw_header("Cache-Control", CACHE_IMMUTABLE);
+        }
+        response.ok()
+    }
+}
@@ -0,0 +1,104 @@
+use crate::error::Result;
+use crate::index::{self, mtime, Listing};
+use crate::repo::Repo;
+use crate::Config;
+use anyhow::Context as _;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+
+/// Files in a repository whose modification invalidates the cached metadata, the empty path is
+/// the repository directory itself which changes when `site.toml` is created or removed
+const REPO_FILES: &[&str] = &["", "site.toml", "config", "description", "HEAD"];
+
+/// In-process cache of repository metadata and the index listing
+///
+/// Entries are validated against file modification times on every access, so the cache never
+/// serves stale data but saves reading and parsing the configuration files.
+#[derive(Default)]
+pub struct RepoCache {
+    repos: Mutex<HashMap<String, CachedRepo>>,
+    listing: Mutex<Option<Arc<Listing>>>,
+}
+
+struct CachedRepo {
+    mtimes: Vec<Option<SystemTime>>,
+    repo: Repo,
+}
+
+impl RepoCache {
+    /// Cached equivalent of [`Repo::open`]
+    pub async fn repo(&self, config: &Config, repo_name: &str) -> Result<Option<Repo>> {
+        let repo_path = config.git_root.join(repo_name);
+        let mut mtimes = Vec::with_capacity(REPO_FILES.len());
+        for file in REPO_FILES {
+            mtimes.push(mtime(&repo_path.join(file)).await);
+        }
+
+        let cached = self.repos.lock().unwrap()
+            .get(repo_name)
+            .filter(|cached| cached.mtimes == mtimes)
+            .map(|cached| cached.repo.clone());
+        if let Some(repo) = cached {
+            return Ok(Some(repo));
+        }
+
+        // only existing repositories are cached, the names of missing ones come straight from
+        // the request URL and would let the cache grow without bounds
+        let repo = Repo::open(config, repo_name).await?;
+        let mut repos = self.repos.lock().unwrap();
+        match &repo {
+            Some(repo) => {
+                repos.insert(repo_name.to_owned(), CachedRepo { mtimes, repo: repo.clone() });
+            }
+            None => {
+                repos.remove(repo_name);
+            }
+        }
+
+        Ok(repo)
+    }
+
+    /// Cached equivalent of opening every repository found by [`index::discover`]
+    pub async fn index(&self, config: &Config) -> Result<Vec<Repo>> {
+        let listing = self.listing(config).await?;
+
+        let mut repos = Vec::with_capacity(listing.names.len());
+        for name in &listing.names {
+            let res = self.repo(config, name).await;
+            match res {
+                Ok(Some(repo)) => repos.push(repo),
+                Ok(None) => {}
+                Err(err) => {
+                    let err = Result::<!>::Err(err)
+                        .with_context(|| format!("reading repo {:?}", name));
+                    log::warn!("{:?}", err.unwrap_err());
+                }
+            }
+        }
+
+        Ok(repos)
+    }
+
+    async fn listing(&self, config: &Config) -> Result<Arc<Listing>> {
+        let cached = self.listing.lock().unwrap().clone();
+        if let Some(listing) = cached {
+            let mut up_to_date = true;
+            for (dir, dir_mtime) in &listing.dirs {
+                if mtime(dir).await != *dir_mtime {
+                    up_to_date = false;
+                    break;
+                }
+            }
+            if up_to_date {
+                return Ok(listing);
+            }
+        }
+
+        let listing = Arc::new(index::discover(config).await?);
+        *self.listing.lock().unwrap() = Some(listing.clone());
+
+        Ok(listing)
+    }
+}
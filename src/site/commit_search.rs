@@ -0,0 +1,163 @@
+//! Searching the commit history of a revision
+
+use crate::error::Result;
+use crate::git_repo::{CommitFilter, GitRepo};
+use crate::nav::Nav;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::view::View;
+use crate::Config;
+use chrono::{DateTime, Duration, NaiveDate};
+use regex::RegexBuilder;
+use rocket::{get, routes, uri, Route, State};
+use std::path::Path;
+use std::time::Instant;
+
+
+/// Default number of commits on a page
+const DEFAULT_LIMIT: usize = 50;
+
+/// Maximum number of commits on a page
+const MAX_LIMIT: usize = 500;
+
+/// Build the filter from the query parameters, the error is meant to be shown to the user
+///
+/// - `author` and `committer` are case insensitive regexes matching `name <email>`
+/// - `message` is a case insensitive substring of the commit message
+/// - `since` and `until` are either dates like `2021-06-30`, both inclusive, or RFC 3339 times
+/// - `paths` are whitespace separated pathspecs, at least one of them has to be changed
+pub fn filter(
+    author: Option<&str>,
+    committer: Option<&str>,
+    message: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    paths: Option<&str>,
+) -> std::result::Result<CommitFilter, String> {
+    let signature = |name: &str, value: Option<&str>| {
+        non_empty(value)
+            .map(|value| RegexBuilder::new(value)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| format!("invalid {} pattern: {}", name, err)))
+            .transpose()
+    };
+    let time = |name: &str, value: Option<&str>, end_of_day: bool| {
+        non_empty(value)
+            .map(|value| parse_time(value, end_of_day)
+                .ok_or_else(|| format!("invalid {} date {:?}, expected YYYY-MM-DD or an RFC 3339 time", name, value)))
+            .transpose()
+    };
+
+    Ok(CommitFilter {
+        author: signature("author", author)?,
+        committer: signature("committer", committer)?,
+        message: non_empty(message).map(str::to_owned),
+        since: time("since", since, false)?,
+        until: time("until", until, true)?,
+        paths: non_empty(paths)
+            .map(|paths| paths.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_default(),
+    })
+}
+
+/// Trimmed `value` unless it's empty
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|value| !value.is_empty())
+}
+
+/// Seconds since the epoch, a date without a time is the start of the day in UTC or the start of
+/// the next day if `end_of_day` is set
+fn parse_time(value: &str, end_of_day: bool) -> Option<i64> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end_of_day { date + Duration::days(1) } else { date };
+        return Some(date.and_hms(0, 0, 0).timestamp());
+    }
+
+    let time = DateTime::parse_from_rfc3339(value).ok()?.timestamp();
+    // an exact time is inclusive as well
+    Some(if end_of_day { time + 1 } else { time })
+}
+
+/// Point in time the search has to be finished by
+pub fn deadline(config: &Config) -> Instant {
+    Instant::now() + std::time::Duration::from_millis(config.search_timeout_ms)
+}
+
+/// Search the commits reachable from `refs`, see [`filter`] for the parameters
+#[get("/<_repo_name>/commits/<refs>?<author>&<committer>&<message>&<since>&<until>&<path>&<skip>&<limit>", rank = 2)]
+pub async fn commits(
+    _repo_name: RepoName,
+    refs: &str,
+    author: Option<&str>,
+    committer: Option<&str>,
+    message: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    path: Option<&str>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    repo: Repo,
+    git_repo: GitRepo,
+    nav: Nav,
+    config: &State<Config>,
+) -> Result<Option<View>> {
+    let commit = match git_repo.find_commit(refs)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let skip = skip.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let form = ctx!{
+        action = uri!(commits(Path::new(&repo.name), refs, _, _, _, _, _, _, _, _)),
+        author = author.unwrap_or(""),
+        committer = committer.unwrap_or(""),
+        message = message.unwrap_or(""),
+        since = since.unwrap_or(""),
+        until = until.unwrap_or(""),
+        path = path.unwrap_or(""),
+    };
+
+    let filter = match filter(author, committer, message, since, until, path) {
+        Ok(filter) => filter,
+        Err(err) => return Ok(Some(render(repo, nav, form, ctx!{ error = err }))),
+    };
+
+    let search = git_repo.search_commits(&commit, &filter, skip, limit, deadline(config))?;
+
+    let page_href = |skip: usize| uri!(commits(Path::new(&repo.name), refs, author, committer, message, since, until, path, Some(skip), Some(limit)));
+    let prev = (skip > 0).then(|| page_href(skip.saturating_sub(limit)));
+    let next = (search.complete && search.commits.len() == limit).then(|| page_href(skip + limit));
+
+    let commits = search.commits.iter()
+        .map(|commit| ctx!{
+            commit,
+            href = uri!(crate::web::tree(Path::new(&repo.name), &commit.id, Path::new(""), _)),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(render(repo, nav, form, ctx!{
+        commits,
+        complete = search.complete,
+        prev,
+        next,
+    })))
+}
+
+fn render(repo: Repo, nav: Nav, form: serde_json::Value, results: serde_json::Value) -> View {
+    View::render("commits", ctx!{
+        repo,
+        form,
+        results,
+        nav,
+        view = "commits",
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        commits,
+    }
+}
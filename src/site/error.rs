@@ -1,8 +1,9 @@
+use crate::view::View;
 use rocket::catch;
 use rocket::http::Status;
 use rocket::request::Request;
 use rocket::response::Responder;
-use rocket_dyn_templates::Template;
+use rocket::serde::json::Json;
 use std::fmt;
 
 /// Error wrapper that implements [`Responder`]
@@ -31,18 +32,33 @@ impl<'req> Responder<'req, 'static> for Error {
     }
 }
 
-#[catch(default)]
-pub fn default_catcher(status: Status, request: &Request) -> Template {
+/// Context describing the error, shared by the HTML and JSON catchers
+fn error_ctx(status: Status, request: &Request) -> serde_json::Value {
     // if the caught error was caused by our `Error` type we have the message stored in cache
     let details = request.local_cache(|| Option::<ErrorDetails>::None)
         .as_ref()
         .map(|details| details.0.to_owned());
 
-    Template::render("error", ctx!{
+    ctx!{
         code = status.code,
         details,
         reason = status.reason().unwrap_or("Unknown"),
-        view = "error",
+    }
+}
+
+#[catch(default)]
+pub fn default_catcher(status: Status, request: &Request) -> View {
+    let mut ctx = error_ctx(status, request);
+    ctx["view"] = "error".into();
+
+    View::render("error", ctx)
+}
+
+/// Catcher for the JSON API, responds with `{"error": {"code", "details", "reason"}}`
+#[catch(default)]
+pub fn api_catcher(status: Status, request: &Request) -> Json<serde_json::Value> {
+    Json(ctx!{
+        error = error_ctx(status, request),
     })
 }
 
@@ -0,0 +1,170 @@
+use crate::error::Result;
+use crate::git_repo::{GitRepo, Signature};
+use crate::index::Index;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::view::Page;
+use crate::web;
+use crate::Config;
+use rocket::http::ContentType;
+use rocket::{get, routes, uri, Route, State};
+use serde::Serialize;
+use std::path::Path;
+
+
+/// Maximum number of entries in a single feed
+const FEED_LENGTH: usize = 30;
+
+#[derive(Serialize)]
+struct Feed {
+    title: String,
+    /// Absolute URL of the feed itself, also used as the feed id
+    href: String,
+    /// Absolute URL of the HTML page the feed is about
+    alternate: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    title: String,
+    id: String,
+    href: String,
+    author: Signature,
+    content: Option<String>,
+}
+
+impl Feed {
+    fn render(self) -> (ContentType, Page) {
+        // Atom requires the feed to have an update time, use the newest entry or the epoch
+        // for empty feeds
+        let updated = self.entries.iter()
+            .map(|entry| entry.author.time)
+            .max()
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| String::from("1970-01-01T00:00:00Z"));
+
+        let content_type = ContentType::new("application", "atom+xml");
+        (content_type, Page::render("atom", ctx!{
+            feed = self,
+            updated,
+        }))
+    }
+}
+
+fn absolute(config: &Config, href: impl std::fmt::Display) -> String {
+    format!("{}{}", config.site_url.trim_end_matches('/'), href)
+}
+
+
+/// Latest commits on a branch, tag or commit
+#[get("/<_repo_name>/atom/<refs>", rank = 2)]
+async fn commits_feed(_repo_name: RepoName, refs: &str, repo: Repo, git_repo: GitRepo, config: &State<Config>) -> Result<Option<(ContentType, Page)>> {
+    let commit = match git_repo.find_commit(refs)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let entries = git_repo.log(&commit, 0, FEED_LENGTH)?
+        .into_iter()
+        .map(|commit| {
+            let href = absolute(config, uri!(web::tree(Path::new(&repo.name), &commit.id, Path::new("/"), _)));
+            Entry {
+                title: commit.summary,
+                id: href.clone(),
+                href,
+                author: commit.author,
+                content: Some(commit.message),
+            }
+        })
+        .collect();
+
+    Ok(Some(Feed {
+        title: format!("{}: commits on {}", repo.name, refs),
+        href: absolute(config, uri!(commits_feed(&repo.name, refs))),
+        alternate: absolute(config, uri!(web::tree(Path::new(&repo.name), refs, Path::new("/"), _))),
+        entries,
+    }.render()))
+}
+
+/// Tags of a repository, newest first
+#[get("/<_repo_name>/tags.atom", rank = 2)]
+async fn tags_feed(_repo_name: RepoName, repo: Repo, git_repo: GitRepo, config: &State<Config>) -> Result<(ContentType, Page)> {
+    let mut tags = git_repo.tags()?;
+    tags.sort_unstable_by(|a, b| Ord::cmp(&b.tagger.time, &a.tagger.time));
+
+    let entries = tags.into_iter()
+        .take(FEED_LENGTH)
+        .map(|tag| {
+            let href = absolute(config, uri!(web::tree(Path::new(&repo.name), &tag.name, Path::new("/"), _)));
+            Entry {
+                title: tag.name,
+                id: format!("{}#{}", href, tag.target),
+                href,
+                author: tag.tagger,
+                content: tag.message,
+            }
+        })
+        .collect();
+
+    Ok(Feed {
+        title: format!("{}: tags", repo.name),
+        href: absolute(config, uri!(tags_feed(&repo.name))),
+        alternate: absolute(config, &repo.href),
+        entries,
+    }.render())
+}
+
+/// Branch tips of all repositories ordered by commit time
+///
+/// Bare repositories usually don't keep reflogs so the commit time of the branch tip is the best
+/// approximation of when it was pushed.
+#[get("/pushes.atom")]
+async fn pushes_feed(index: Index, config: &State<Config>) -> Result<(ContentType, Page)> {
+    let mut entries = Vec::new();
+
+    for repo in &index.repos {
+        // a single broken repository shouldn't take down the feed for all of them
+        let git_repo = match GitRepo::open(repo) {
+            Ok(git_repo) => git_repo,
+            Err(err) => {
+                log::warn!("{:?}", err);
+                continue;
+            }
+        };
+        for branch in git_repo.branches()? {
+            // resolve the full ref name, a short name could also be a tag or an abbreviated id
+            let commit = match git_repo.find_commit(&format!("refs/heads/{}", branch.name))? {
+                Some(commit) => crate::git_repo::Commit::from(&commit),
+                None => continue,
+            };
+            let href = absolute(config, uri!(web::tree(Path::new(&repo.name), &commit.id, Path::new("/"), _)));
+            entries.push(Entry {
+                title: format!("{}/{}: {}", repo.name, branch.name, commit.summary),
+                id: format!("{}#{}", href, branch.name),
+                href,
+                author: commit.committer,
+                content: Some(commit.message),
+            });
+        }
+    }
+
+    entries.sort_unstable_by(|a, b| Ord::cmp(&b.author.time, &a.author.time));
+    entries.truncate(FEED_LENGTH);
+
+    Ok(Feed {
+        title: String::from("recent pushes"),
+        href: absolute(config, uri!(pushes_feed)),
+        alternate: absolute(config, "/"),
+        entries,
+    }.render())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        commits_feed,
+        tags_feed,
+        pushes_feed,
+    }
+}
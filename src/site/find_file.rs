@@ -0,0 +1,260 @@
+//! Listing all files of a revision and finding them by fuzzy matching their paths
+
+use crate::error::Result;
+use crate::git_repo::{GitRepo, Object};
+use crate::nav::Nav;
+use crate::render_cache::RenderCache;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::search::Part;
+use crate::view::View;
+use rocket::http::ContentType;
+use rocket::{get, routes, uri, Route, State};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+
+/// Maximum number of matches shown on the find file page
+const MAX_RESULTS: usize = 100;
+
+/// Score of every matched character
+const SCORE_MATCH: i32 = 16;
+
+/// Bonus for a character following the previous matched one
+const BONUS_CONSECUTIVE: i32 = 8;
+
+/// Bonus for matching the first character of a path component
+const BONUS_COMPONENT: i32 = 8;
+
+/// Bonus for matching the first character of a word, after `_`, `-`, `.`, a space or a lowercase
+/// letter followed by an uppercase one
+const BONUS_WORD: i32 = 6;
+
+/// Bonus for matching a character of the file name rather than of a directory
+const BONUS_FILE_NAME: i32 = 2;
+
+/// Penalty of every character skipped between two matched ones
+const PENALTY_GAP: i32 = 1;
+
+#[derive(Serialize)]
+pub struct FileMatch {
+    pub path: String,
+    pub score: i32,
+    /// The path split into alternating unmatched and matched parts
+    pub parts: Vec<Part>,
+}
+
+/// Paths of all blobs in the tree of `refs`, `None` if `refs` doesn't exist
+///
+/// The list only depends on the tree so it's cached by its id, failures aren't cached.
+pub fn tree_paths(git_repo: &GitRepo, refs: &str, render_cache: &RenderCache) -> Result<Option<Arc<Vec<String>>>> {
+    let tree = match git_repo.find_subtree_object_by_path(refs, Path::new(""), None)? {
+        Some(Object::Tree(tree)) => tree,
+        _ => return Ok(None),
+    };
+
+    if let Some(paths) = render_cache.get("files", tree.id(), "") {
+        return Ok(Some(paths));
+    }
+
+    let paths = git_repo.tree_blobs(&tree)?
+        .into_iter()
+        .map(|(path, _)| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    Ok(Some(render_cache.get_or_render("files", tree.id(), "", || paths)))
+}
+
+/// Match `query` against all `paths`, best matches first
+///
+/// The characters of the query have to appear in the path in the same order, case insensitively,
+/// and whitespace in the query is ignored. Matches at the start of path components and words and
+/// runs of consecutive characters score higher, ties are broken by the shorter path.
+pub fn fuzzy_find(paths: &[String], query: &str) -> Vec<FileMatch> {
+    let query = query.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+
+    let mut matches = paths.iter()
+        .filter_map(|path| {
+            let (score, positions) = fuzzy_match(path, &query)?;
+            Some(FileMatch {
+                path: path.to_owned(),
+                score,
+                parts: highlight(path, &positions),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    matches.sort_by(|a, b| b.score.cmp(&a.score)
+        .then_with(|| a.path.len().cmp(&b.path.len()))
+        .then_with(|| a.path.cmp(&b.path)));
+    matches
+}
+
+/// Score of the best match of the lowercase `query` in `path` and the char indices it matched
+fn fuzzy_match(path: &str, query: &[char]) -> Option<(i32, Vec<usize>)> {
+    let chars = path.chars().collect::<Vec<_>>();
+    let lower = chars.iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+
+    // cheap check for a subsequence before scoring
+    let mut rest = lower.iter();
+    if !query.iter().all(|q| rest.any(|c| c == q)) {
+        return None;
+    }
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    let file_name = chars.iter().rposition(|&c| c == '/').map_or(0, |slash| slash + 1);
+    let bonus = |j: usize| {
+        let bonus = match j.checked_sub(1).map(|prev| chars[prev]) {
+            None | Some('/') => BONUS_COMPONENT,
+            Some(prev) if "_-. ".contains(prev) => BONUS_WORD,
+            Some(prev) if prev.is_lowercase() && chars[j].is_uppercase() => BONUS_WORD,
+            _ => 0,
+        };
+        if j >= file_name { bonus + BONUS_FILE_NAME } else { bonus }
+    };
+
+    // scores[i][j] is the best score of matching query[..=i] with query[i] at chars[j] and
+    // from[i][j] the position query[i - 1] was matched at
+    let n = chars.len();
+    let mut scores = vec![vec![None; n]; query.len()];
+    let mut from = vec![vec![0; n]; query.len()];
+
+    for j in 0..n {
+        if lower[j] == query[0] {
+            scores[0][j] = Some(SCORE_MATCH + bonus(j));
+        }
+    }
+
+    for i in 1..query.len() {
+        // best score of a previous match followed by a gap, with the position of that match
+        let mut gap: Option<(i32, usize)> = None;
+
+        for j in 1..n {
+            let adjacent = scores[i - 1][j - 1];
+            let consecutive = adjacent.map(|score| (score + BONUS_CONSECUTIVE, j - 1));
+            let best = better(consecutive, gap);
+
+            // one more character is skipped for the matches before the next position
+            gap = better(
+                gap.map(|(score, k)| (score - PENALTY_GAP, k)),
+                adjacent.map(|score| (score - PENALTY_GAP, j - 1)),
+            );
+
+            if lower[j] != query[i] {
+                continue;
+            }
+            if let Some((score, k)) = best {
+                scores[i][j] = Some(score + SCORE_MATCH + bonus(j));
+                from[i][j] = k;
+            }
+        }
+    }
+
+    let last = query.len() - 1;
+    let (mut j, score) = scores[last].iter()
+        .enumerate()
+        .filter_map(|(j, score)| score.map(|score| (j, score)))
+        .max_by_key(|&(j, score)| (score, std::cmp::Reverse(j)))?;
+
+    let mut positions = vec![j];
+    for i in (1..=last).rev() {
+        j = from[i][j];
+        positions.push(j);
+    }
+    positions.reverse();
+
+    Some((score, positions))
+}
+
+/// The candidate with the higher score, the first one on ties
+fn better(a: Option<(i32, usize)>, b: Option<(i32, usize)>) -> Option<(i32, usize)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.0 >= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Split `path` into parts, marking the chars at `positions`
+fn highlight(path: &str, positions: &[usize]) -> Vec<Part> {
+    let mut parts = Vec::<Part>::new();
+    for (i, c) in path.chars().enumerate() {
+        let is_match = positions.binary_search(&i).is_ok();
+        match parts.last_mut() {
+            Some(part) if part.is_match == is_match => part.text.push(c),
+            _ => parts.push(Part { text: c.to_string(), is_match }),
+        }
+    }
+    parts
+}
+
+/// All paths of `refs` as plain text, one per line like `git ls-files`
+#[get("/<_repo_name>/files/<refs>", rank = 2)]
+async fn files(_repo_name: RepoName, refs: &str, git_repo: GitRepo, render_cache: &State<RenderCache>) -> Result<Option<(ContentType, String)>> {
+    let paths = match tree_paths(&git_repo, refs, render_cache)? {
+        Some(paths) => paths,
+        None => return Ok(None),
+    };
+
+    let mut text = String::new();
+    for path in paths.iter() {
+        text.push_str(path);
+        text.push('\n');
+    }
+
+    Ok(Some((ContentType::Plain, text)))
+}
+
+/// Find a file of `refs` by fuzzy matching its path against `q`
+#[get("/<_repo_name>/find/<refs>?<q>", rank = 2)]
+pub async fn find(
+    _repo_name: RepoName,
+    refs: &str,
+    q: Option<&str>,
+    repo: Repo,
+    git_repo: GitRepo,
+    nav: Nav,
+    render_cache: &State<RenderCache>,
+) -> Result<Option<View>> {
+    let paths = match tree_paths(&git_repo, refs, render_cache)? {
+        Some(paths) => paths,
+        None => return Ok(None),
+    };
+    let paths = paths.as_slice();
+
+    let query = q.unwrap_or("");
+    let matches = fuzzy_find(paths, query);
+    let total = matches.len();
+    let files = matches.into_iter()
+        .take(MAX_RESULTS)
+        .map(|file| ctx!{
+            href = uri!(crate::web::tree(Path::new(&repo.name), refs, Path::new(&file.path), _)),
+            file,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(View::render("find", ctx!{
+        form = ctx!{
+            action = uri!(find(Path::new(&repo.name), refs, _)),
+            q = query,
+        },
+        results = ctx!{ files, total, paths = paths.len() },
+        repo,
+        nav,
+        view = "find",
+    })))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        files,
+        find,
+    }
+}
@@ -1,11 +1,16 @@
 use crate::error::{Error, Result};
 use crate::repo::Repo;
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, FixedOffset, TimeZone};
 use rocket::http::Status;
 use rocket::outcome::{try_outcome, Outcome};
 use rocket::request::{FromRequest, Request};
-use serde::Serialize;
-use std::path::Path;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 
 pub struct GitRepo {
@@ -17,9 +22,137 @@ pub struct Branch {
     pub name: String,
 }
 
+#[derive(Serialize)]
+pub struct Tag {
+    pub name: String,
+    /// Id of the commit the tag points to
+    pub target: String,
+    /// Message of an annotated tag, lightweight tags don't have any
+    pub message: Option<String>,
+    /// Tagger of an annotated tag or committer of the target commit for lightweight tags
+    pub tagger: Signature,
+}
+
+#[derive(Serialize)]
+pub struct Commit {
+    pub id: String,
+    pub summary: String,
+    pub message: String,
+    pub author: Signature,
+    pub committer: Signature,
+}
+
+#[derive(Serialize)]
+pub struct CommitDetails {
+    #[serde(flatten)]
+    pub commit: Commit,
+    pub tree: String,
+    pub parents: Vec<String>,
+    /// Files changed compared to the first parent
+    pub changes: Vec<Change>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    pub status: &'static str,
+    pub path: Option<PathBuf>,
+    /// Original path of renamed and copied files
+    pub old_path: Option<PathBuf>,
+}
+
+/// Conditions a commit has to satisfy to be found by [`GitRepo::search_commits`]
+#[derive(Default)]
+pub struct CommitFilter {
+    /// Matched against `name <email>` of the author
+    pub author: Option<Regex>,
+    /// Matched against `name <email>` of the committer
+    pub committer: Option<Regex>,
+    /// Case insensitive substring of the message
+    pub message: Option<String>,
+    /// Author time in seconds since the epoch, inclusive
+    pub since: Option<i64>,
+    /// Author time in seconds since the epoch, exclusive
+    pub until: Option<i64>,
+    /// Commits have to change a path matching one of these pathspecs, unless it's empty
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CommitSearch {
+    pub commits: Vec<Commit>,
+    /// `false` if the search ran out of time before walking the whole history
+    pub complete: bool,
+}
+
+/// Statistics of all blobs in a tree and its subtrees
+#[derive(Serialize, Deserialize)]
+pub struct TreeSummary {
+    pub files: usize,
+    /// Total size of the files in bytes
+    pub size: usize,
+    /// Largest files, sorted by size in descending order
+    pub largest: Vec<SizedPath>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SizedPath {
+    pub path: PathBuf,
+    pub size: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub time: DateTime<FixedOffset>,
+}
+
+impl From<&git2::Commit<'_>> for Commit {
+    fn from(commit: &git2::Commit<'_>) -> Commit {
+        let message = String::from_utf8_lossy(commit.message_bytes())
+            .to_string();
+        let summary = message.lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        Commit {
+            id: commit.id().to_string(),
+            summary,
+            message,
+            author: Signature::from(&commit.author()),
+            committer: Signature::from(&commit.committer()),
+        }
+    }
+}
+
+impl From<&git2::Signature<'_>> for Signature {
+    fn from(signature: &git2::Signature<'_>) -> Signature {
+        let when = signature.when();
+        // git stores the offset in minutes, invalid offsets fall back to UTC
+        let offset = FixedOffset::east_opt(when.offset_minutes() * 60)
+            .unwrap_or_else(|| FixedOffset::east(0));
+
+        Signature {
+            name: String::from_utf8_lossy(signature.name_bytes()).to_string(),
+            email: String::from_utf8_lossy(signature.email_bytes()).to_string(),
+            time: offset.timestamp(when.seconds(), 0),
+        }
+    }
+}
+
 pub enum Object<'repo> {
     Tree(git2::Tree<'repo>),
     Blob(git2::Blob<'repo>),
+    /// Blob over the size limit, its content wasn't loaded
+    LargeBlob(LargeBlob),
+}
+
+pub struct LargeBlob {
+    pub id: git2::Oid,
+    pub size: usize,
 }
 
 impl<'repo> Object<'repo> {
@@ -28,6 +161,7 @@ impl<'repo> Object<'repo> {
         match self {
             Object::Blob(blob) => blob,
             Object::Tree(_) => panic!("called `Object::unwrap_blob` on a `Object::Tree` value`"),
+            Object::LargeBlob(_) => panic!("called `Object::unwrap_blob` on a `Object::LargeBlob` value`"),
         }
     }
 }
@@ -39,17 +173,25 @@ impl<'req> FromRequest<'req> for GitRepo {
     async fn from_request(request: &'req Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
         let repo = try_outcome!(request.guard::<Repo>().await);
 
-        let res = git2::Repository::open_bare(&repo.path)
-            .with_context(|| format!("reading git repo {:?}", &repo.path));
-        match res {
-            Ok(git_repo) => Outcome::Success(GitRepo { git_repo }),
-            Err(err) => Outcome::Failure((Status::InternalServerError, err.into())),
+        match GitRepo::open(&repo) {
+            Ok(git_repo) => Outcome::Success(git_repo),
+            Err(err) => Outcome::Failure((Status::InternalServerError, err)),
         }
     }
 }
 
 impl GitRepo {
-    pub fn find_subtree_object_by_path(&self, branch_tag_commit: &str, path: &Path) -> Result<Option<Object<'_>>> {
+    pub fn open(repo: &Repo) -> Result<GitRepo> {
+        let git_repo = git2::Repository::open_bare(&repo.path)
+            .with_context(|| format!("reading git repo {:?}", &repo.path))?;
+        Ok(GitRepo { git_repo })
+    }
+
+    /// Find the tree or blob at `path`
+    ///
+    /// Blobs larger than `max_blob_size` are returned as `Object::LargeBlob` without reading their
+    /// content, `None` loads blobs of any size.
+    pub fn find_subtree_object_by_path(&self, branch_tag_commit: &str, path: &Path, max_blob_size: Option<usize>) -> Result<Option<Object<'_>>> {
         let tree = match self.find_ref_root_tree(branch_tag_commit)? {
             Some(tree) => tree,
             None => return Ok(None),
@@ -59,16 +201,25 @@ impl GitRepo {
             return Ok(Some(Object::Tree(tree)));
         }
 
-        let object = match tree.get_path(path) {
-            Ok(entry) => {
-                let object = entry.to_object(&self.git_repo)
-                    .context("finding path object")?;
-                object
-            },
+        let entry = match tree.get_path(path) {
+            Ok(entry) => entry,
             Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
             Err(err) => Err(err).context("finding tree path")?,
         };
 
+        if let (Some(max_blob_size), Some(git2::ObjectType::Blob)) = (max_blob_size, entry.kind()) {
+            // reading just the header doesn't inflate the object
+            let (size, _) = self.git_repo.odb()
+                .and_then(|odb| odb.read_header(entry.id()))
+                .context("reading blob header")?;
+            if size > max_blob_size {
+                return Ok(Some(Object::LargeBlob(LargeBlob { id: entry.id(), size })));
+            }
+        }
+
+        let object = entry.to_object(&self.git_repo)
+            .context("finding path object")?;
+
         match object.kind() {
             Some(git2::ObjectType::Tree) => Ok(Some(Object::Tree(object.into_tree().unwrap()))),
             Some(git2::ObjectType::Blob) => Ok(Some(Object::Blob(object.into_blob().unwrap()))),
@@ -127,6 +278,269 @@ impl GitRepo {
         Ok(None)
     }
 
+    /// Resolve a branch, tag or commit id into the commit it points to
+    pub fn find_commit(&self, branch_tag_commit: &str) -> Result<Option<git2::Commit<'_>>> {
+        let object = match self.git_repo.revparse_single(branch_tag_commit) {
+            Ok(object) => object,
+            Err(err) if is_not_found(&err) => return Ok(None),
+            Err(err) => Err(err).with_context(|| format!("resolving revision {:?}", branch_tag_commit))?,
+        };
+
+        match object.peel_to_commit() {
+            Ok(commit) => Ok(Some(commit)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("finding commit for {:?}", branch_tag_commit))?,
+        }
+    }
+
+    /// Format a single commit the same way `git format-patch` would
+    ///
+    /// `patch_no` and `total_patches` end up in the `Subject: [PATCH n/m]` header.
+    pub fn format_patch(&self, commit: &git2::Commit<'_>, patch_no: usize, total_patches: usize) -> Result<String> {
+        // `Diff::format_email` panics on commits without a summary line
+        if commit.summary_bytes().is_none() {
+            return Err(anyhow!("commit {} has an empty message", commit.id()).into());
+        }
+
+        let mut diff = self.commit_diff(commit)?;
+        let email = diff.format_email(patch_no, total_patches, commit, None)
+            .with_context(|| format!("formatting patch for commit {}", commit.id()))?;
+
+        Ok(String::from_utf8_lossy(&email).into_owned())
+    }
+
+    /// Diff of the commit against its first parent, or against an empty tree for root commits
+    fn commit_diff(&self, commit: &git2::Commit<'_>) -> Result<git2::Diff<'_>> {
+        let tree = commit.tree()
+            .with_context(|| format!("finding tree of commit {}", commit.id()))?;
+        let parent_tree = match commit.parent_count() {
+            0 => None,
+            _ => {
+                let parent = commit.parent(0)
+                    .with_context(|| format!("finding parent of commit {}", commit.id()))?;
+                let tree = parent.tree()
+                    .with_context(|| format!("finding tree of commit {}", parent.id()))?;
+                Some(tree)
+            }
+        };
+
+        let diff = self.git_repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+            .with_context(|| format!("diffing commit {}", commit.id()))?;
+        Ok(diff)
+    }
+
+    pub fn commit_details(&self, commit: &git2::Commit<'_>) -> Result<CommitDetails> {
+        let mut diff = self.commit_diff(commit)?;
+        diff.find_similar(None)
+            .with_context(|| format!("finding renames in commit {}", commit.id()))?;
+        let stats = diff.stats()
+            .with_context(|| format!("computing diff stats of commit {}", commit.id()))?;
+
+        let changes = diff.deltas()
+            .map(|delta| {
+                let status = match delta.status() {
+                    git2::Delta::Added => "added",
+                    git2::Delta::Deleted => "deleted",
+                    git2::Delta::Renamed => "renamed",
+                    git2::Delta::Copied => "copied",
+                    git2::Delta::Typechange => "typechange",
+                    _ => "modified",
+                };
+                let path = delta.new_file().path()
+                    .or_else(|| delta.old_file().path())
+                    .map(Path::to_owned);
+                let old_path = matches!(delta.status(), git2::Delta::Renamed | git2::Delta::Copied)
+                    .then(|| delta.old_file().path().map(Path::to_owned))
+                    .flatten();
+                Change { status, path, old_path }
+            })
+            .collect();
+
+        Ok(CommitDetails {
+            commit: Commit::from(commit),
+            tree: commit.tree_id().to_string(),
+            parents: commit.parent_ids().map(|oid| oid.to_string()).collect(),
+            changes,
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        })
+    }
+
+    /// Format the commits reachable from `to` but not from `from` as an mbox
+    ///
+    /// Commits are ordered from oldest to newest and merge commits are skipped, same as `git
+    /// format-patch from..to` does.
+    pub fn format_patch_series(&self, from: &str, to: &str) -> Result<Option<String>> {
+        let (from, to) = match (self.find_commit(from)?, self.find_commit(to)?) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Ok(None),
+        };
+
+        let mut revwalk = self.git_repo.revwalk()
+            .context("creating revwalk")?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+            .context("setting revwalk sorting")?;
+        revwalk.push(to.id())
+            .with_context(|| format!("pushing commit {} to revwalk", to.id()))?;
+        revwalk.hide(from.id())
+            .with_context(|| format!("hiding commit {} from revwalk", from.id()))?;
+
+        let commits = revwalk
+            .map(|res| -> Result<_> {
+                let oid = res.context("walking commits")?;
+                let commit = self.git_repo.find_commit(oid)
+                    .with_context(|| format!("finding commit {}", oid))?;
+                Ok(commit)
+            })
+            .filter(|res| !matches!(res, Ok(commit) if commit.parent_count() > 1))
+            .collect::<Result<Vec<_>>>()?;
+
+        let total = commits.len();
+        let mbox = commits.iter()
+            .enumerate()
+            .map(|(idx, commit)| self.format_patch(commit, idx + 1, total))
+            .collect::<Result<String>>()?;
+
+        Ok(Some(mbox))
+    }
+
+    /// List at most `limit` commits reachable from `commit`, newest first
+    pub fn log(&self, commit: &git2::Commit<'_>, skip: usize, limit: usize) -> Result<Vec<Commit>> {
+        let mut revwalk = self.git_repo.revwalk()
+            .context("creating revwalk")?;
+        revwalk.set_sorting(git2::Sort::TIME)
+            .context("setting revwalk sorting")?;
+        revwalk.push(commit.id())
+            .with_context(|| format!("pushing commit {} to revwalk", commit.id()))?;
+
+        revwalk
+            .skip(skip)
+            .take(limit)
+            .map(|res| -> Result<Commit> {
+                let oid = res.context("walking commits")?;
+                let commit = self.git_repo.find_commit(oid)
+                    .with_context(|| format!("finding commit {}", oid))?;
+                Ok(Commit::from(&commit))
+            })
+            .collect()
+    }
+
+    /// Find commits reachable from `commit` matching `filter`, newest first
+    ///
+    /// `skip` and `limit` apply to the matching commits. The walk stops at `deadline`, the result
+    /// is then marked as incomplete.
+    pub fn search_commits(&self, commit: &git2::Commit<'_>, filter: &CommitFilter, skip: usize, limit: usize, deadline: Instant) -> Result<CommitSearch> {
+        let mut revwalk = self.git_repo.revwalk()
+            .context("creating revwalk")?;
+        revwalk.set_sorting(git2::Sort::TIME)
+            .context("setting revwalk sorting")?;
+        revwalk.push(commit.id())
+            .with_context(|| format!("pushing commit {} to revwalk", commit.id()))?;
+
+        let message = filter.message.as_ref().map(|message| message.to_lowercase());
+        let mut diff_options = git2::DiffOptions::new();
+        for path in &filter.paths {
+            diff_options.pathspec(path);
+        }
+
+        let mut commits = Vec::new();
+        let mut skipped = 0;
+
+        for res in revwalk {
+            if Instant::now() > deadline {
+                return Ok(CommitSearch { commits, complete: false });
+            }
+
+            let oid = res.context("walking commits")?;
+            let commit = self.git_repo.find_commit(oid)
+                .with_context(|| format!("finding commit {}", oid))?;
+
+            let author = commit.author();
+            let time = author.when().seconds();
+            if filter.since.map_or(false, |since| time < since)
+                || filter.until.map_or(false, |until| time >= until)
+            {
+                continue;
+            }
+
+            let matches_signature = |regex: &Option<Regex>, signature: &git2::Signature<'_>| {
+                regex.as_ref().map_or(true, |regex| regex.is_match(&signature.to_string()))
+            };
+            if !matches_signature(&filter.author, &author) || !matches_signature(&filter.committer, &commit.committer()) {
+                continue;
+            }
+
+            if let Some(message) = &message {
+                let commit_message = String::from_utf8_lossy(commit.message_bytes()).to_lowercase();
+                if !commit_message.contains(message) {
+                    continue;
+                }
+            }
+
+            if !filter.paths.is_empty() {
+                // merges are compared against their first parent like `git log --first-parent`
+                let tree = commit.tree()
+                    .with_context(|| format!("finding tree of commit {}", oid))?;
+                let parent_tree = match commit.parents().next() {
+                    Some(parent) => Some(parent.tree()
+                        .with_context(|| format!("finding tree of commit {}", parent.id()))?),
+                    None => None,
+                };
+                let diff = self.git_repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut diff_options))
+                    .with_context(|| format!("diffing commit {}", oid))?;
+                if diff.deltas().len() == 0 {
+                    continue;
+                }
+            }
+
+            if skipped < skip {
+                skipped += 1;
+                continue;
+            }
+            commits.push(Commit::from(&commit));
+            if commits.len() == limit {
+                break;
+            }
+        }
+
+        Ok(CommitSearch { commits, complete: true })
+    }
+
+    pub fn tags(&self) -> Result<Vec<Tag>> {
+        let names = self.git_repo.tag_names(None)
+            .context("iterating tags")?;
+
+        names.iter()
+            .flatten()
+            .filter_map(|name| -> Option<Result<Tag>> {
+                let res = self.git_repo.revparse_single(&format!("refs/tags/{}", name))
+                    .with_context(|| format!("finding tag {:?}", name));
+                let object = match res {
+                    Ok(object) => object,
+                    Err(err) => return Some(Err(err.into())),
+                };
+                // tags can point to any object, we only care about the ones pointing to commits
+                let commit = object.peel_to_commit().ok()?;
+                let annotated = object.into_tag().ok();
+
+                let message = annotated.as_ref()
+                    .and_then(|tag| tag.message_bytes())
+                    .map(|message| String::from_utf8_lossy(message).to_string());
+                let tagger = annotated.as_ref()
+                    .and_then(|tag| tag.tagger())
+                    .map(|tagger| Signature::from(&tagger))
+                    .unwrap_or_else(|| Signature::from(&commit.committer()));
+
+                Some(Ok(Tag {
+                    name: name.to_string(),
+                    target: commit.id().to_string(),
+                    message,
+                    tagger,
+                }))
+            })
+            .collect()
+    }
+
     pub fn branches(&self) -> Result<Vec<Branch>> {
         self.git_repo
             .branches(Some(git2::BranchType::Local))
@@ -146,4 +560,208 @@ impl GitRepo {
             .context("collecting branches")
             .map_err(Error::from)
     }
+
+    /// Longest ancestor of `path`, including `path` itself, which exists in the tree of `refs`
+    ///
+    /// Returns `None` if `refs` doesn't point to a commit.
+    pub fn closest_existing_path(&self, refs: &str, path: &Path) -> Result<Option<PathBuf>> {
+        let commit = match self.find_commit(refs)? {
+            Some(commit) => commit,
+            None => return Ok(None),
+        };
+        let tree = commit.tree()
+            .with_context(|| format!("finding tree of commit {}", commit.id()))?;
+
+        // the root is an empty path which `get_path` doesn't accept
+        let existing = path.ancestors()
+            .find(|ancestor| ancestor.as_os_str().is_empty() || tree.get_path(ancestor).is_ok())
+            .unwrap_or_else(|| Path::new(""));
+
+        Ok(Some(existing.to_owned()))
+    }
+
+    /// Size of a blob read from the object header without inflating the content
+    pub fn blob_size(&self, oid: git2::Oid) -> Result<usize> {
+        let odb = self.git_repo.odb()
+            .context("opening object database")?;
+        let (size, _) = odb.read_header(oid)
+            .with_context(|| format!("reading header of object {}", oid))?;
+        Ok(size)
+    }
+
+    pub fn find_blob(&self, oid: git2::Oid) -> Result<git2::Blob<'_>> {
+        let blob = self.git_repo.find_blob(oid)
+            .with_context(|| format!("finding blob {}", oid))?;
+        Ok(blob)
+    }
+
+    /// Pass the content of the blob `oid` to `write` in chunks of up to `chunk_size` bytes
+    ///
+    /// Loose objects are streamed without holding the whole content in memory, libgit2 can't
+    /// stream packed objects so those are loaded first.
+    pub fn write_blob(&self, oid: git2::Oid, chunk_size: usize, mut write: impl FnMut(&[u8]) -> io::Result<()>) -> Result<()> {
+        let odb = self.git_repo.odb()
+            .context("opening object database")?;
+
+        if let Ok((mut reader, _, _)) = odb.reader(oid) {
+            let mut buf = vec![0; chunk_size];
+            loop {
+                let len = reader.read(&mut buf)
+                    .with_context(|| format!("reading blob {}", oid))?;
+                if len == 0 {
+                    return Ok(());
+                }
+                write(&buf[..len])
+                    .with_context(|| format!("writing blob {}", oid))?;
+            }
+        }
+
+        let blob = self.find_blob(oid)?;
+        for chunk in blob.content().chunks(chunk_size) {
+            write(chunk)
+                .with_context(|| format!("writing blob {}", oid))?;
+        }
+        Ok(())
+    }
+
+    /// Paths and ids of all blobs in `tree` and its subtrees
+    pub fn tree_blobs(&self, tree: &git2::Tree<'_>) -> Result<Vec<(PathBuf, git2::Oid)>> {
+        let mut blobs = Vec::new();
+        tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                let path = Path::new(dir).join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+                blobs.push((path, entry.id()));
+            }
+            git2::TreeWalkResult::Ok
+        }).with_context(|| format!("walking tree {}", tree.id()))?;
+
+        Ok(blobs)
+    }
+
+    /// Number of entries in a tree
+    pub fn tree_len(&self, oid: git2::Oid) -> Result<usize> {
+        let tree = self.git_repo.find_tree(oid)
+            .with_context(|| format!("finding tree {}", oid))?;
+        Ok(tree.len())
+    }
+
+    /// Count and sum up the sizes of all blobs reachable from the tree `oid`, keeping the
+    /// `largest` ones
+    pub fn tree_summary(&self, oid: git2::Oid, largest: usize) -> Result<TreeSummary> {
+        let tree = self.git_repo.find_tree(oid)
+            .with_context(|| format!("finding tree {}", oid))?;
+        let odb = self.git_repo.odb()
+            .context("opening object database")?;
+        let mut summary = TreeSummary {
+            files: 0,
+            size: 0,
+            largest: Vec::new(),
+        };
+        let mut error = None;
+
+        let res = tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            let size = match odb.read_header(entry.id()) {
+                Ok((size, _)) => size,
+                Err(err) => {
+                    error = Some(err);
+                    return git2::TreeWalkResult::Abort;
+                }
+            };
+
+            summary.files += 1;
+            summary.size += size;
+            if summary.largest.len() < largest || summary.largest.last().map_or(false, |file| file.size < size) {
+                let path = Path::new(dir).join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+                let at = summary.largest.partition_point(|file| file.size >= size);
+                summary.largest.insert(at, SizedPath { path, size });
+                summary.largest.truncate(largest);
+            }
+            git2::TreeWalkResult::Ok
+        });
+
+        // aborting the walk makes it fail too, the original error is more useful
+        if let Some(err) = error {
+            Err(err).with_context(|| format!("reading blob sizes of tree {}", tree.id()))?;
+        }
+        res.with_context(|| format!("walking tree {}", tree.id()))?;
+
+        Ok(summary)
+    }
+
+    /// Target of a symlink, stored by git as the content of its blob
+    pub fn symlink_target(&self, oid: git2::Oid) -> Result<String> {
+        let blob = self.git_repo.find_blob(oid)
+            .with_context(|| format!("finding symlink blob {}", oid))?;
+        Ok(String::from_utf8_lossy(blob.content()).to_string())
+    }
+
+    /// Submodule URLs from `.gitmodules` at the revision, keyed by the submodule path
+    pub fn submodule_urls(&self, branch_tag_commit: &str) -> Result<HashMap<PathBuf, String>> {
+        let blob = match self.find_subtree_object_by_path(branch_tag_commit, Path::new(".gitmodules"), None)? {
+            Some(Object::Blob(blob)) => blob,
+            _ => return Ok(HashMap::new()),
+        };
+
+        Ok(parse_gitmodules(&String::from_utf8_lossy(blob.content())))
+    }
+}
+
+/// Parse the `path` and `url` of every `[submodule "name"]` section
+///
+/// `.gitmodules` uses the git config syntax, only the subset written by `git submodule add` is
+/// supported: no includes, continuation lines or quoted values with escapes.
+fn parse_gitmodules(text: &str) -> HashMap<PathBuf, String> {
+    let mut urls = HashMap::new();
+    let mut section = None::<(Option<String>, Option<String>)>;
+
+    let mut flush = |section: Option<(Option<String>, Option<String>)>| {
+        if let Some((Some(path), Some(url))) = section {
+            urls.insert(PathBuf::from(path), url);
+        }
+    };
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            flush(section.take());
+            if line.starts_with("[submodule") {
+                section = Some((None, None));
+            }
+            continue;
+        }
+
+        let (path, url) = match &mut section {
+            Some(section) => section,
+            None => continue,
+        };
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"').to_owned();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "path" => *path = Some(value),
+                "url" => *url = Some(value),
+                _ => {}
+            }
+        }
+    }
+    flush(section);
+
+    urls
+}
+
+/// Errors which mean the requested revision simply doesn't exist or can't be peeled to the
+/// requested type, these should result in a 404 instead of an error
+fn is_not_found(err: &git2::Error) -> bool {
+    matches!(err.code(),
+        git2::ErrorCode::NotFound |
+        git2::ErrorCode::InvalidSpec |
+        git2::ErrorCode::Ambiguous |
+        git2::ErrorCode::Peel
+    )
 }
@@ -0,0 +1,169 @@
+use crate::error::Result;
+use crate::git_repo::{GitRepo, Object};
+use crate::nav::Nav;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::view::View;
+use crate::web;
+use crate::Config;
+use anyhow::Context as _;
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::http::uri::fmt::{FromUriParam, Query};
+use rocket::{get, routes, uri, Route, State};
+use serde::Serialize;
+use std::path::Path;
+
+
+/// Bytes on a single line of the dump
+const LINE_LENGTH: usize = 16;
+
+/// Largest range which can be requested at once as a multiple of `hexdump_page_size`, every byte
+/// turns into a few hundred bytes of HTML
+const MAX_PAGES: usize = 4;
+
+/// Supported sizes of the word groups in bytes
+const GROUPINGS: &[usize] = &[1, 2, 4, 8];
+
+/// Byte offset given either in decimal or in hex with a `0x` prefix
+pub struct ByteOffset(pub usize);
+
+impl<'v> FromFormField<'v> for ByteOffset {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let value = field.value.trim();
+        let res = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(digits) => usize::from_str_radix(digits, 16),
+            None => value.parse(),
+        };
+
+        match res {
+            Ok(offset) => Ok(ByteOffset(offset)),
+            Err(_) => Err(form::Error::validation(format!("invalid byte offset {:?}", value)).into()),
+        }
+    }
+}
+
+impl FromUriParam<Query, usize> for ByteOffset {
+    type Target = usize;
+
+    fn from_uri_param(param: usize) -> usize {
+        param
+    }
+}
+
+#[derive(Serialize)]
+struct Line {
+    offset: usize,
+    /// Offset formatted the way `xxd` does
+    label: String,
+    words: Vec<Vec<Byte>>,
+    /// Spaces aligning the ASCII column of the last, shorter line
+    padding: String,
+}
+
+#[derive(Serialize)]
+struct Byte {
+    offset: usize,
+    hex: String,
+    /// Printable representation, `.` for anything but graphic ASCII characters
+    ascii: char,
+}
+
+/// Hex viewer of a range of a blob
+///
+/// `offset` and `length` select the bytes shown, `group` the number of bytes in a word. The
+/// selected bytes are highlighted client side from the URL fragment, eg. `#0x10-0x1f`.
+#[get("/<_repo_name>/hex/<refs>/<path..>?<offset>&<length>&<group>", rank = 2)]
+pub async fn hex(
+    _repo_name: RepoName,
+    refs: &str,
+    path: RepoPath,
+    offset: Option<ByteOffset>,
+    length: Option<usize>,
+    group: Option<usize>,
+    repo: Repo,
+    git_repo: GitRepo,
+    nav: Nav,
+    config: &State<Config>,
+) -> Result<Option<View>> {
+    let object = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_blob_size))
+        .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))?;
+    let blob = match object {
+        Some(Object::Blob(blob)) => blob,
+        Some(Object::LargeBlob(blob)) => return web::render_large_blob(&blob, refs, &path, repo, nav).map(Some),
+        _ => return Ok(None),
+    };
+
+    let size = blob.size();
+    let offset = offset.map(|offset| offset.0).unwrap_or(0).min(size);
+    let max_length = config.hexdump_page_size.max(LINE_LENGTH) * MAX_PAGES;
+    let length = length.unwrap_or(config.hexdump_page_size).clamp(1, max_length);
+    let end = size.min(offset.saturating_add(length));
+    let group = group.filter(|group| GROUPINGS.contains(group)).unwrap_or(2);
+
+    let lines = hex_lines(&blob.content()[offset..end], offset, group);
+
+    let page_href = |offset: usize| uri!(hex(Path::new(&repo.name), refs, &*path, Some(offset), Some(length), Some(group)));
+    let prev = (offset > 0).then(|| page_href(offset.saturating_sub(length)));
+    let next = (end < size).then(|| page_href(end));
+    let groupings = GROUPINGS.iter()
+        .map(|&size| ctx!{
+            size,
+            href = uri!(hex(Path::new(&repo.name), refs, &*path, Some(offset), Some(length), Some(size))),
+        })
+        .collect::<Vec<_>>();
+
+    let name = path.file_name().unwrap()
+        .to_string_lossy()
+        .to_string();
+    let raw_href = uri!(web::raw(Path::new(&repo.name), refs, &*path));
+    let action = uri!(hex(Path::new(&repo.name), refs, &*path, _, _, _));
+
+    Ok(Some(View::render("hexdump", ctx!{
+        repo,
+        blob = ctx!{ name, size, raw_href },
+        dump = ctx!{ action, offset, end, length, group, groupings, prev, next, lines },
+        nav,
+        view = "hexdump",
+    })))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        hex,
+    }
+}
+
+
+/// Split `data` into lines of `LINE_LENGTH` bytes and words of `group` bytes, `start` is the
+/// offset of `data` within the blob
+fn hex_lines(data: &[u8], start: usize, group: usize) -> Vec<Line> {
+    data.chunks(LINE_LENGTH)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let offset = start + line * LINE_LENGTH;
+            let words: Vec<Vec<Byte>> = chunk.chunks(group)
+                .enumerate()
+                .map(|(word, bytes)| bytes.iter()
+                    .enumerate()
+                    .map(|(i, &byte)| Byte {
+                        offset: offset + word * group + i,
+                        hex: format!("{:02x}", byte),
+                        ascii: if byte.is_ascii_graphic() { byte as char } else { '.' },
+                    })
+                    .collect())
+                .collect();
+
+            // every word is preceded by a space and every byte takes two characters
+            let width = |bytes: usize, words: usize| bytes * 2 + words;
+            let missing = width(LINE_LENGTH, LINE_LENGTH / group) - width(chunk.len(), words.len());
+
+            Line {
+                offset,
+                label: format!("{:08x}", offset),
+                words,
+                padding: " ".repeat(missing),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use crate::repo::Repo;
+use crate::Config;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+
+/// Version of the rendered output, included in entity tags so that upgrading the site invalidates
+/// pages cached by clients
+const TEMPLATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// `Cache-Control` for pages addressed by a full commit id, those can never change
+pub(crate) const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// `Cache-Control` for everything else, clients may cache but have to revalidate with the ETag
+const CACHE_REVALIDATE: &str = "no-cache";
+
+/// `Cache-Control` for pages which differ between users and mustn't be stored by shared caches
+const CACHE_PRIVATE: &str = "private, no-cache";
+
+/// HTTP cache validators for a page showing a single git object
+#[derive(Clone)]
+pub struct Validators {
+    etag: String,
+    immutable: bool,
+    private: bool,
+}
+
+impl Validators {
+    /// `oid` is the object shown on the page, `refs` the revision from the URL
+    pub fn new(oid: git2::Oid, refs: &str, repo: &Repo, config: &Config) -> Validators {
+        // repository and site settings change the rendered output too
+        let mut hasher = DefaultHasher::new();
+        repo.name.hash(&mut hasher);
+        repo.description.hash(&mut hasher);
+        repo.default_branch.hash(&mut hasher);
+        repo.readme_path.hash(&mut hasher);
+        repo.lang_override.hash(&mut hasher);
+        config.site_name.hash(&mut hasher);
+        config.site_url.hash(&mut hasher);
+        config.footer.hash(&mut hasher);
+        config.logo.hash(&mut hasher);
+        config.ssh_url_base.hash(&mut hasher);
+        config.http_url_base.hash(&mut hasher);
+        config.static_dir.hash(&mut hasher);
+        config.max_blob_size.hash(&mut hasher);
+        config.max_text_size.hash(&mut hasher);
+        config.hexdump_page_size.hash(&mut hasher);
+
+        let immutable = refs.len() == 40 && git2::Oid::from_str(refs).is_ok();
+
+        Validators {
+            etag: format!("{}-{:016x}-{}", oid, hasher.finish(), TEMPLATE_VERSION),
+            immutable,
+            private: false,
+        }
+    }
+
+    /// Include `value` in the entity tag, for inputs of the page other than the object and the
+    /// settings, eg. links to other repositories
+    pub fn depends_on(mut self, value: impl Hash) -> Validators {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        self.etag.push_str(&format!("-{:016x}", hasher.finish()));
+        self
+    }
+
+    /// Mark the page as personalized for the user when `personalized` is set, eg. when it shows
+    /// push URLs
+    pub fn personalized(mut self, personalized: bool) -> Validators {
+        if personalized {
+            self.etag.push_str("-user");
+            self.private = true;
+        }
+        self
+    }
+
+    /// Respond with `304 Not Modified` if the client already has this `variant` of the page,
+    /// otherwise with the response produced by `respond`
+    pub fn respond<'r>(
+        &self,
+        request: &Request<'_>,
+        variant: &str,
+        respond: impl FnOnce() -> response::Result<'r>,
+    ) -> response::Result<'r> {
+        let etag = format!("\"{}-{}\"", self.etag, variant);
+
+        let mut response = if is_fresh(request, &etag) {
+            Response::build()
+                .status(Status::NotModified)
+                .finalize()
+        } else {
+            respond()?
+        };
+
+        let cache_control = match (self.private, self.immutable) {
+            (true, _) => CACHE_PRIVATE,
+            (false, true) => CACHE_IMMUTABLE,
+            (false, false) => CACHE_REVALIDATE,
+        };
+        response.set_raw_header("ETag", etag);
+        response.set_raw_header("Cache-Control", cache_control);
+
+        Ok(response)
+    }
+}
+
+/// Check whether `If-None-Match` contains `etag`
+///
+/// `If-None-Match` uses the weak comparison so `W/` prefixes are ignored.
+fn is_fresh(request: &Request<'_>, etag: &str) -> bool {
+    request.headers()
+        .get("If-None-Match")
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .map(|tag| tag.strip_prefix("W/").unwrap_or(tag))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+
+/// Responder which adds cache validators to `R`
+pub struct Cached<R> {
+    pub validators: Validators,
+    pub inner: R,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Cached<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let Cached { validators, inner } = self;
+        validators.respond(request, "raw", || inner.respond_to(request))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::repo_name::RepoName;
 use crate::Config;
 use rocket::fs::NamedFile;
 use rocket::{get, routes, Route, State};
@@ -9,18 +10,18 @@ async fn serve_file(root: &Path, repo_name: &Path, file: &Path) -> Option<NamedF
 }
 
 #[get("/<repo_name>/HEAD", rank = 1)]
-async fn head(repo_name: PathBuf, config: &State<Config>) -> Option<NamedFile> {
-    serve_file(&config.git_root, &repo_name, Path::new("HEAD")).await
+async fn head(repo_name: RepoName, config: &State<Config>) -> Option<NamedFile> {
+    serve_file(&config.git_root, repo_name.as_ref(), Path::new("HEAD")).await
 }
 
 #[get("/<repo_name>/info/refs", rank = 1)]
-async fn info_refs(repo_name: PathBuf, config: &State<Config>) -> Option<NamedFile> {
-    serve_file(&config.git_root, &repo_name, Path::new("info/refs")).await
+async fn info_refs(repo_name: RepoName, config: &State<Config>) -> Option<NamedFile> {
+    serve_file(&config.git_root, repo_name.as_ref(), Path::new("info/refs")).await
 }
 
 #[get("/<repo_name>/objects/<object..>", rank = 1)]
-async fn objects(repo_name: PathBuf, object: PathBuf, config: &State<Config>) -> Option<NamedFile> {
-    serve_file(&config.git_root, &repo_name, &Path::new("objects").join(object)).await
+async fn objects(repo_name: RepoName, object: PathBuf, config: &State<Config>) -> Option<NamedFile> {
+    serve_file(&config.git_root, repo_name.as_ref(), &Path::new("objects").join(object)).await
 }
 
 pub fn routes() -> Vec<Route> {
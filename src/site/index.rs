@@ -1,13 +1,16 @@
+use crate::cache::RepoCache;
 use crate::error::Result;
 use crate::repo::Repo;
+use crate::repo_name::is_git_dir;
+use crate::Config;
 use anyhow::Context as _;
-use futures::stream::{StreamExt, TryStreamExt};
 use rocket::http::Status;
 use rocket::outcome::Outcome;
 use rocket::request::FromRequest;
 use rocket::{Request, State};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use tokio::fs;
-use tokio_stream::wrappers::ReadDirStream;
 
 
 pub struct Index {
@@ -19,44 +22,90 @@ impl<'req> FromRequest<'req> for Index {
     type Error = crate::error::Error;
 
     async fn from_request(request: &'req Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
-        let config = request.guard::<&State<crate::Config>>().await.unwrap();
+        let config = request.guard::<&State<Config>>().await.unwrap();
+        let cache = request.guard::<&State<RepoCache>>().await.unwrap();
 
-        let res = fs::read_dir(&config.git_root).await
-            .with_context(|| format!("reading directory git_root={:?}", &config.git_root));
-        let read_dir = match res {
-            Ok(read_dir) => read_dir,
-            Err(err) => return Outcome::Failure((Status::InternalServerError, err.into())),
+        match cache.index(config).await {
+            Ok(repos) => Outcome::Success(Index { repos }),
+            Err(err) => Outcome::Failure((Status::InternalServerError, err)),
+        }
+    }
+}
+
+/// Names of repositories found in `git_root` together with the directories which were searched
+pub struct Listing {
+    pub names: Vec<String>,
+    /// Modification times of the searched directories, when none of them changed the listing is
+    /// still up to date
+    pub dirs: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+/// Find repositories in `git_root` and its subdirectories up to `repo_depth` levels deep
+///
+/// Directories which are not git repositories are searched recursively, repositories themselves
+/// are never descended into.
+pub async fn discover(config: &Config) -> Result<Listing> {
+    let git_root = config.git_root.as_path();
+    let mut listing = Listing {
+        names: Vec::new(),
+        dirs: Vec::new(),
+    };
+    let mut dirs = vec![(PathBuf::from(git_root), 1)];
+
+    while let Some((dir, depth)) = dirs.pop() {
+        // take the mtime before reading so that concurrent changes invalidate the listing
+        listing.dirs.push((dir.clone(), mtime(&dir).await));
+
+        let res = read_dir(&dir).await
+            .with_context(|| format!("reading directory {:?}", &dir));
+        let entries = match res {
+            Ok(entries) => entries,
+            // not being able to read git_root itself is an error, subdirectories are skipped
+            Err(err) if dir == git_root => return Err(err.into()),
+            Err(err) => {
+                log::warn!("{:?}", err);
+                continue;
+            }
         };
 
-        let res = ReadDirStream::new(read_dir)
-            .map(|res| Ok(res.context("reading direntry")?))
-            .try_filter_map(async move |entry| {
-                let metadata = entry.metadata().await
-                    .context("reading direntry")?;
-                Ok(metadata.is_dir().then(|| entry))
-            })
-            .try_filter_map(async move |entry| -> Result<_> {
-                let path = entry.path();
-                let name = entry.file_name();
-                let name = name.to_string_lossy();
-                let res = Repo::open(&path, &name).await;
-                match res {
-                    Ok(repo) => Ok(repo),
-                    Err(err) => {
-                        let err = Result::<!>::Err(err)
-                            .with_context(|| format!("reading repo {:?}", &path));
-                        log::warn!("{:?}", err.unwrap_err());
-                        Ok(None)
-                    }
+        for path in entries {
+            if !is_git_dir(&path).await {
+                if depth < config.repo_depth {
+                    dirs.push((path, depth + 1));
                 }
-            })
-            .try_collect::<Vec<_>>().await;
+                continue;
+            }
 
-        let repos = match res {
-            Ok(repos) => repos,
-            Err(err) => return Outcome::Failure((Status::InternalServerError, err.into())),
-        };
+            let name = path.strip_prefix(git_root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            listing.names.push(name);
+        }
+    }
+
+    Ok(listing)
+}
 
-        Outcome::Success(Index { repos })
+/// Modification time of a file or directory, `None` if it doesn't exist
+pub async fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// List subdirectories of `dir`
+async fn read_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut read_dir = fs::read_dir(dir).await?;
+    let mut dirs = Vec::new();
+
+    while let Some(entry) = read_dir.next_entry().await.context("reading direntry")? {
+        let metadata = entry.metadata().await
+            .context("reading direntry")?;
+        if metadata.is_dir() {
+            dirs.push(entry.path());
+        }
     }
+
+    Ok(dirs)
 }
@@ -0,0 +1,163 @@
+//! Detection of media files which browsers can preview inline
+
+use serde::Serialize;
+use std::path::Path;
+
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Audio,
+    Video,
+    Pdf,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Media {
+    pub kind: MediaKind,
+    pub mime: &'static str,
+    /// Pixel dimensions of images, `None` if they couldn't be read from the header
+    pub dimensions: Option<Dimensions>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Detect the media type from the magic bytes at the start of `data`, falling back to the file
+/// extension of `name` for formats without a reliable signature
+pub fn detect(name: &str, data: &[u8]) -> Option<Media> {
+    let (kind, mime) = match sniff(data) {
+        // ogg is a container for both audio and video
+        Some((MediaKind::Audio, "audio/ogg")) if extension(name).as_deref() == Some("ogv") => {
+            (MediaKind::Video, "video/ogg")
+        }
+        Some(detected) => detected,
+        // MPEG audio frames without an ID3 tag and icons are too easy to mistake for other data
+        None => match extension(name).as_deref() {
+            Some("mp3") => (MediaKind::Audio, "audio/mpeg"),
+            Some("ico") if data.starts_with(b"\x00\x00\x01\x00") => (MediaKind::Image, "image/x-icon"),
+            _ => return None,
+        },
+    };
+
+    let dimensions = match kind {
+        MediaKind::Image => image_dimensions(mime, data),
+        _ => None,
+    };
+
+    Some(Media { kind, mime, dimensions })
+}
+
+/// Guess the media type only from the file extension, used when the content isn't available
+pub fn detect_by_extension(name: &str) -> Option<Media> {
+    let (kind, mime) = match extension(name)?.as_str() {
+        "png" => (MediaKind::Image, "image/png"),
+        "jpg" | "jpeg" => (MediaKind::Image, "image/jpeg"),
+        "gif" => (MediaKind::Image, "image/gif"),
+        "webp" => (MediaKind::Image, "image/webp"),
+        "bmp" => (MediaKind::Image, "image/bmp"),
+        "ico" => (MediaKind::Image, "image/x-icon"),
+        "mp3" => (MediaKind::Audio, "audio/mpeg"),
+        "ogg" | "oga" | "opus" => (MediaKind::Audio, "audio/ogg"),
+        "flac" => (MediaKind::Audio, "audio/flac"),
+        "wav" => (MediaKind::Audio, "audio/wav"),
+        "mp4" | "m4v" => (MediaKind::Video, "video/mp4"),
+        "webm" => (MediaKind::Video, "video/webm"),
+        "ogv" => (MediaKind::Video, "video/ogg"),
+        "pdf" => (MediaKind::Pdf, "application/pdf"),
+        _ => return None,
+    };
+
+    Some(Media { kind, mime, dimensions: None })
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name).extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+fn sniff(data: &[u8]) -> Option<(MediaKind, &'static str)> {
+    let riff_type = data.get(8..12).filter(|_| data.starts_with(b"RIFF"));
+
+    let detected = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        (MediaKind::Image, "image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        (MediaKind::Image, "image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        (MediaKind::Image, "image/gif")
+    } else if riff_type == Some(&b"WEBP"[..]) {
+        (MediaKind::Image, "image/webp")
+    } else if data.starts_with(b"BM") && data.len() > 26 {
+        (MediaKind::Image, "image/bmp")
+    } else if data.starts_with(b"ID3") {
+        (MediaKind::Audio, "audio/mpeg")
+    } else if data.starts_with(b"OggS") {
+        (MediaKind::Audio, "audio/ogg")
+    } else if data.starts_with(b"fLaC") {
+        (MediaKind::Audio, "audio/flac")
+    } else if riff_type == Some(&b"WAVE"[..]) {
+        (MediaKind::Audio, "audio/wav")
+    } else if data.get(4..8) == Some(&b"ftyp"[..]) {
+        (MediaKind::Video, "video/mp4")
+    } else if data.starts_with(b"\x1a\x45\xdf\xa3") {
+        (MediaKind::Video, "video/webm")
+    } else if data.starts_with(b"%PDF-") {
+        (MediaKind::Pdf, "application/pdf")
+    } else {
+        return None;
+    };
+
+    Some(detected)
+}
+
+/// Read the image dimensions from the file header
+fn image_dimensions(mime: &str, data: &[u8]) -> Option<Dimensions> {
+    let (width, height) = image_size(mime, data)?;
+    Some(Dimensions { width, height })
+}
+
+fn image_size(mime: &str, data: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |at: usize| data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let le32 = |at: usize| data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let be16 = |at: usize| data.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32);
+    let le16 = |at: usize| data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+    let le24 = |at: usize| data.get(at..at + 3).map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]));
+
+    match mime {
+        // IHDR is always the first chunk
+        "image/png" => Some((be32(16)?, be32(20)?)),
+        "image/gif" => Some((le16(6)?, le16(8)?)),
+        // the height is negative for top-down bitmaps
+        "image/bmp" => Some((le32(18)?, (le32(22)? as i32).unsigned_abs())),
+        "image/webp" => match data.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = le32(21)?;
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => {
+            // walk the segments until a start of frame marker
+            let mut at = 2;
+            loop {
+                if *data.get(at)? != 0xff {
+                    return None;
+                }
+                let marker = *data.get(at + 1)?;
+                match marker {
+                    0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                        return Some((be16(at + 7)?, be16(at + 5)?));
+                    }
+                    _ => at += 2 + be16(at + 2)? as usize,
+                }
+            }
+        }
+        _ => None,
+    }
+}
@@ -1,4 +1,5 @@
 use crate::repo::Repo;
+use crate::repo_name::RepoName;
 use crate::repo_path::RepoPath;
 use crate::web;
 use anyhow::Context as _;
@@ -8,7 +9,7 @@ use rocket::outcome::Outcome;
 use rocket::request::{FromRequest, Request};
 use rocket::uri;
 use serde::Serialize;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 
 #[derive(Serialize)]
@@ -75,7 +76,7 @@ impl<'req> FromRequest<'req> for Nav {
                                 .map(|fname| fname.to_string_lossy().to_string())
                                 // repository root has an empty path -> file_name() returns None
                                 .unwrap_or_else(|| repo.name.clone());
-                            let href = uri!(web::tree(Path::new(&repo.name), refs, path));
+                            let href = uri!(web::tree(Path::new(&repo.name), refs, path, _));
                             Segment { name, href }
                         })
                     .collect::<Vec<_>>()
@@ -96,7 +97,7 @@ impl<'req> FromRequest<'req> for Nav {
 
             RefNav {
                 current: current.to_string(),
-                href: uri!(web::refs(&repo.name, current, &path)),
+                href: uri!(web::refs(&repo.name, current, &path, _)),
             }
         });
 
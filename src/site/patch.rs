@@ -0,0 +1,43 @@
+use crate::error::Result;
+use crate::git_repo::GitRepo;
+use crate::repo_name::RepoName;
+use rocket::http::ContentType;
+use rocket::{get, routes, Route};
+
+
+/// `git format-patch` output of a single commit
+#[get("/<_repo_name>/commit/<file>", rank = 2)]
+async fn commit_patch(_repo_name: RepoName, file: &str, git_repo: GitRepo) -> Result<Option<(ContentType, String)>> {
+    let rev = match file.strip_suffix(".patch") {
+        Some(rev) => rev,
+        None => return Ok(None),
+    };
+    let commit = match git_repo.find_commit(rev)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    let patch = git_repo.format_patch(&commit, 1, 1)?;
+
+    Ok(Some((ContentType::Plain, patch)))
+}
+
+/// `git format-patch a..b` output of a range of commits concatenated into an mbox
+#[get("/<_repo_name>/compare/<range>", rank = 2)]
+async fn compare_mbox(_repo_name: RepoName, range: &str, git_repo: GitRepo) -> Result<Option<(ContentType, String)>> {
+    let (from, to) = match range.strip_suffix(".mbox").and_then(|range| range.split_once("...")) {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let mbox = git_repo.format_patch_series(from, to)?;
+
+    Ok(mbox.map(|mbox| (ContentType::new("application", "mbox"), mbox)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        commit_patch,
+        compare_mbox,
+    }
+}
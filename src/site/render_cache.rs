@@ -0,0 +1,297 @@
+use crate::Config;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+
+/// Bounded cache of rendered page fragments
+///
+/// Fragments are keyed by the view which rendered them, the git object they were rendered from
+/// and a string of any other options that influence the output. Since objects are immutable the
+/// entries never have to be invalidated, only evicted when the cache grows over
+/// `render_cache_size` bytes. When `render_cache_dir` is configured the fragments are also stored
+/// on disk as JSON and survive restarts, evicting an entry deletes its file so the directory stays
+/// within the same bound.
+pub struct RenderCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    max_entry: usize,
+    dir: Option<PathBuf>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Suffix of temporary files, concurrent writes of the same fragment mustn't share one
+    tmp_counter: AtomicU64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Entries keyed by [`key`], which is also the name of their file on disk
+    entries: HashMap<String, Entry>,
+    /// Keys ordered from the least recently used
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    size: usize,
+}
+
+struct Entry {
+    /// `None` for fragments stored on disk by a previous run which haven't been read yet
+    value: Option<Arc<dyn Any + Send + Sync>>,
+    size: usize,
+    tick: u64,
+}
+
+impl RenderCache {
+    /// Fragments stored in `render_cache_dir` by a previous run are picked up
+    pub fn new(config: &Config) -> RenderCache {
+        let render_cache = RenderCache {
+            inner: Mutex::default(),
+            capacity: config.render_cache_size,
+            max_entry: config.render_cache_entry_size,
+            dir: config.render_cache_dir.clone(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            tmp_counter: AtomicU64::new(0),
+        };
+        render_cache.load_disk();
+        render_cache
+    }
+
+    /// Get the fragment from the cache, `None` if it isn't cached
+    pub fn get<T>(&self, view: &'static str, oid: git2::Oid, options: &str) -> Option<Arc<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        if self.capacity == 0 {
+            return None;
+        }
+        let key = key(view, oid, options);
+
+        let cached = self.inner.lock().unwrap().get(&key);
+        match cached {
+            Some(Some(value)) => {
+                if let Ok(value) = value.downcast::<T>() {
+                    self.count(true, view, oid);
+                    return Some(value);
+                }
+            }
+            Some(None) => {
+                if let Some((value, size)) = self.read_disk::<T>(&key) {
+                    self.count(true, view, oid);
+                    let value = Arc::new(value);
+                    self.insert(key, value.clone(), size);
+                    return Some(value);
+                }
+            }
+            None => {}
+        }
+        None
+    }
+
+    /// Get the fragment from the cache or `render` and store it
+    pub fn get_or_render<T, F>(&self, view: &'static str, oid: git2::Oid, options: &str, render: F) -> Arc<T>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + 'static,
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.get(view, oid, options) {
+            return value;
+        }
+        if self.capacity == 0 {
+            return Arc::new(render());
+        }
+        let key = key(view, oid, options);
+
+        self.count(false, view, oid);
+        let value = render();
+        let data = match serde_json::to_vec(&value) {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn!("serializing {} fragment for {}: {}", view, oid, err);
+                return Arc::new(value);
+            }
+        };
+
+        let value = Arc::new(value);
+        if self.fits(data.len()) {
+            self.write_disk(&key, &data);
+            self.insert(key, value.clone(), data.len());
+        }
+        value
+    }
+
+    fn count(&self, hit: bool, view: &str, oid: git2::Oid) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        log::debug!("render cache {} for {} {} (hits={}, misses={})",
+            if hit { "hit" } else { "miss" },
+            view,
+            oid,
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        );
+    }
+
+    fn fits(&self, size: usize) -> bool {
+        size <= self.max_entry && size <= self.capacity
+    }
+
+    fn insert<T: Send + Sync + 'static>(&self, key: String, value: Arc<T>, size: usize) {
+        if !self.fits(size) {
+            return;
+        }
+        let evicted = self.inner.lock().unwrap().insert(key, Some(value as Arc<dyn Any + Send + Sync>), size, self.capacity);
+        self.remove_disk(&evicted);
+    }
+
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.json", key)))
+    }
+
+    /// Track the fragments in `render_cache_dir` as if they were inserted from the oldest to the
+    /// newest one, files over the capacity are deleted along with leftovers of interrupted writes
+    fn load_disk(&self) {
+        let dir = match &self.dir {
+            Some(dir) if self.capacity > 0 => dir,
+            _ => return,
+        };
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("reading render cache directory {:?}: {}", dir, err);
+                return;
+            }
+        };
+
+        let mut files = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => {}
+                Some("tmp") => {
+                    if let Err(err) = std::fs::remove_file(&path) {
+                        log::warn!("removing render cache file {:?}: {}", path, err);
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+            let (key, metadata) = match (path.file_stem().and_then(|stem| stem.to_str()), entry.metadata()) {
+                (Some(key), Ok(metadata)) => (key.to_owned(), metadata),
+                _ => continue,
+            };
+            files.push((metadata.modified().ok(), key, metadata.len() as usize));
+        }
+        files.sort_unstable();
+
+        let mut evicted = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        for (_, key, size) in files {
+            if self.fits(size) {
+                evicted.extend(inner.insert(key, None, size, self.capacity));
+            } else {
+                evicted.push(key);
+            }
+        }
+        drop(inner);
+
+        self.remove_disk(&evicted);
+    }
+
+    fn read_disk<T: DeserializeOwned>(&self, key: &str) -> Option<(T, usize)> {
+        let path = self.disk_path(key)?;
+        let data = std::fs::read(&path).ok()?;
+        match serde_json::from_slice(&data) {
+            Ok(value) => Some((value, data.len())),
+            Err(err) => {
+                log::warn!("ignoring corrupted render cache file {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+
+    fn write_disk(&self, key: &str, data: &[u8]) {
+        let path = match self.disk_path(key) {
+            Some(path) => path,
+            None => return,
+        };
+        // write into a temporary file first so that readers never see a partial fragment
+        let tmp = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = path.with_extension(format!("{}.{}.tmp", std::process::id(), tmp));
+        let res = std::fs::write(&tmp_path, data)
+            .and_then(|()| std::fs::rename(&tmp_path, &path));
+        if let Err(err) = res {
+            log::warn!("writing render cache file {:?}: {}", path, err);
+        }
+    }
+
+    fn remove_disk(&self, keys: &[String]) {
+        for key in keys {
+            let path = match self.disk_path(key) {
+                Some(path) => path,
+                None => return,
+            };
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => log::warn!("removing render cache file {:?}: {}", path, err),
+            }
+        }
+    }
+}
+
+/// Key of a fragment, the options are hashed so that the key can be used as a file name
+fn key(view: &str, oid: git2::Oid, options: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    options.hash(&mut hasher);
+    format!("{}-{}-{:016x}", view, oid, hasher.finish())
+}
+
+impl Inner {
+    /// `None` if the key isn't cached, `Some(None)` if its fragment has to be read from disk
+    fn get(&mut self, key: &str) -> Option<Option<Arc<dyn Any + Send + Sync>>> {
+        let entry = self.entries.get_mut(key)?;
+
+        self.recency.remove(&entry.tick);
+        self.tick += 1;
+        entry.tick = self.tick;
+        self.recency.insert(self.tick, key.to_owned());
+
+        Some(entry.value.clone())
+    }
+
+    /// Returns the keys of the evicted entries
+    fn insert(&mut self, key: String, value: Option<Arc<dyn Any + Send + Sync>>, size: usize, capacity: usize) -> Vec<String> {
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.tick);
+            self.size -= old.size;
+        }
+
+        // evict the least recently used entries until the new one fits
+        let mut evicted = Vec::new();
+        while self.size + size > capacity {
+            let tick = match self.recency.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+            let key = self.recency.remove(&tick).unwrap();
+            if let Some(old) = self.entries.remove(&key) {
+                self.size -= old.size;
+            }
+            evicted.push(key);
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry { value, size, tick: self.tick });
+        self.size += size;
+
+        evicted
+    }
+}
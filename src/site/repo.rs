@@ -1,4 +1,7 @@
+use crate::cache::RepoCache;
 use crate::error::Result;
+use crate::repo_name::{is_git_dir, RepoName};
+use crate::user::User;
 use anyhow::Context;
 use glob::Pattern;
 use rocket::http::uri::Origin;
@@ -6,13 +9,16 @@ use rocket::http::Status;
 use rocket::outcome::Outcome;
 use rocket::request::{FromRequest, Request};
 use rocket::{uri, State};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
 #[derive(Serialize, Clone)]
 pub struct Repo {
     pub name: String,
+    /// Location on the server, kept out of the API and templates
+    #[serde(skip)]
     pub path: PathBuf,
     pub href: Origin<'static>,
     pub description: Option<String>,
@@ -21,17 +27,26 @@ pub struct Repo {
 
     #[serde(skip)]
     pub lang_override: Vec<(Pattern, String)>,
+    /// Users allowed to push, kept out of the templates
+    #[serde(skip)]
+    pub push_access: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct File {
     pub name: String,
     pub path: PathBuf,
     pub href: Origin<'static>,
     pub mode: FileMode,
+    /// Size of files in bytes
+    pub size: Option<usize>,
+    /// Number of entries in directories
+    pub entries: Option<usize>,
+    pub symlink: Option<Symlink>,
+    pub submodule: Option<Submodule>,
 }
 
-#[derive(Serialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy)]
 pub enum FileMode {
     #[serde(rename = "file")]
     File,
@@ -39,6 +54,27 @@ pub enum FileMode {
     Dir,
     #[serde(rename = "exe")]
     Exe,
+    #[serde(rename = "link")]
+    Symlink,
+    #[serde(rename = "submodule")]
+    Submodule,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Symlink {
+    pub target: String,
+    /// Link to the target if it points inside the repository
+    pub href: Option<Origin<'static>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Submodule {
+    /// Commit the submodule is pinned to
+    pub commit: String,
+    /// URL from `.gitmodules`
+    pub url: Option<String>,
+    /// Link to the pinned commit if the submodule is hosted on this site
+    pub href: Option<Origin<'static>>,
 }
 
 impl FileMode {
@@ -47,6 +83,8 @@ impl FileMode {
             0o100_644 => Some(FileMode::File),
             0o100_755 => Some(FileMode::Exe),
             0o040_000 => Some(FileMode::Dir),
+            0o120_000 => Some(FileMode::Symlink),
+            0o160_000 => Some(FileMode::Submodule),
             _ => {
                 log::warn!("unknown file mode {:#o}", mode);
                 None
@@ -60,13 +98,13 @@ mod config {
     use std::collections::HashMap;
     use std::path::PathBuf;
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, Default)]
     pub struct Config {
         /// Repository metadata
+        #[serde(default)]
         pub repo: Repo,
 
-        #[serde(default)]
-        pub access: Access,
+        pub access: Option<Access>,
 
         /// Associations of `(language-code, glob-pattern)` which override the default javascript
         /// language detection
@@ -74,31 +112,85 @@ mod config {
         pub lang_override: HashMap<String, String>,
     }
 
-    #[derive(Deserialize)]
+    impl Config {
+        /// Merge two configs, values in `self` take precedence over `fallback`
+        pub fn or(self, fallback: Config) -> Config {
+            let mut lang_override = fallback.lang_override;
+            lang_override.extend(self.lang_override);
+
+            Config {
+                repo: self.repo.or(fallback.repo),
+                access: self.access.or(fallback.access),
+                lang_override,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Default)]
     pub struct Repo {
-        pub default_branch: String,
+        pub default_branch: Option<String>,
         pub description: Option<String>,
         pub readme: Option<PathBuf>,
     }
 
+    impl Repo {
+        /// Fill in the fields missing in `self` from `fallback`
+        pub fn or(self, fallback: Repo) -> Repo {
+            Repo {
+                default_branch: self.default_branch.or(fallback.default_branch),
+                description: self.description.or(fallback.description),
+                readme: self.readme.or(fallback.readme),
+            }
+        }
+    }
+
     #[derive(Deserialize, Default)]
     pub struct Access {
+        /// Names of the users allowed to push
+        #[serde(default)]
+        pub push: Vec<String>,
     }
 }
 
 impl Repo {
-    pub async fn open(repo_path: &Path, repo_name: &str) -> Result<Option<Repo>> {
-        let config_path = repo_path.join("site.toml");
-        if !config_path.exists() {
+    /// Whether `user` is allowed to push to the repository
+    pub fn can_push(&self, user: Option<&User>) -> bool {
+        user.map_or(false, |user| self.push_access.iter().any(|name| *name == user.name))
+    }
+
+    /// Directory part of a nested repository name, empty for top level repositories
+    pub fn group(&self) -> &str {
+        self.name.rsplit_once('/')
+            .map(|(group, _)| group)
+            .unwrap_or("")
+    }
+
+    pub async fn open(site_config: &crate::Config, repo_name: &str) -> Result<Option<Repo>> {
+        let repo_path = site_config.git_root.join(repo_name);
+        if !is_git_dir(&repo_path).await {
             return Ok(None);
         }
-        let config = {
+
+        let config_path = repo_path.join("site.toml");
+        let toml_config = if config_path.exists() {
             let data = fs::read(&config_path).await
                 .with_context(|| format!("reading repo config {:?}", config_path))?;
 
-            toml::de::from_slice::<config::Config>(&data)
-                .with_context(|| format!("parsing repo config {:?}", config_path))?
+            let config = toml::de::from_slice::<config::Config>(&data)
+                .with_context(|| format!("parsing repo config {:?}", config_path))?;
+            Some(config)
+        } else {
+            None
+        };
+        let git_config = read_git_config(&repo_path)?;
+
+        let mut config = match (toml_config, git_config) {
+            (None, None) if !site_config.show_unconfigured => return Ok(None),
+            // site.toml takes precedence over the settings stored in git config
+            (toml_config, git_config) => toml_config.unwrap_or_default()
+                .or(git_config.unwrap_or_default()),
         };
+        config.repo = config.repo.or(read_git_metadata(&repo_path).await);
 
         let lang_override = config.lang_override
             .into_iter()
@@ -125,32 +217,128 @@ impl Repo {
 
         Ok(Some(Repo {
             name: repo_name.to_string(),
-            path: repo_path.to_owned(),
-            href: uri!(crate::web::home(&repo_name)),
+            path: repo_path,
+            href: uri!(crate::web::home(repo_name)),
             description: config.repo.description,
-            default_branch: config.repo.default_branch,
+            // HEAD of a bare repository points to the default branch, it is only missing when the
+            // repository is broken
+            default_branch: config.repo.default_branch
+                .unwrap_or_else(|| String::from("master")),
             lang_override,
             readme_path,
+            push_access: config.access
+                .map(|access| access.push)
+                .unwrap_or_default(),
         }))
     }
 }
 
+/// Default content of the `description` file created by `git init`
+const DEFAULT_DESCRIPTION: &str = "Unnamed repository; edit this file 'description' to name the repository.";
+
+/// Read the `gitsite.*` settings from the repository's git config
+///
+/// The keys mirror `site.toml`: `gitsite.defaultBranch`, `gitsite.description`, `gitsite.readme`,
+/// `gitsite.langOverride.<lang>` and `gitsite.access.<key>`. Access keys can be given multiple
+/// times and are always read as lists. Returns `None` if there are no `gitsite.*` keys.
+fn read_git_config(repo_path: &Path) -> Result<Option<config::Config>> {
+    let git_config = git2::Config::open(&repo_path.join("config"))
+        .with_context(|| format!("reading git config of {:?}", repo_path))?;
+    let entries = git_config.entries(Some("^gitsite\\."))
+        .with_context(|| format!("iterating git config of {:?}", repo_path))?;
+
+    let mut config = config::Config::default();
+    let mut access = HashMap::<String, Vec<String>>::new();
+    let mut found = false;
+
+    for entry in &entries {
+        let entry = entry
+            .with_context(|| format!("reading git config entry of {:?}", repo_path))?;
+        let (name, value) = match (entry.name(), entry.value()) {
+            (Some(name), Some(value)) => (name, value.to_owned()),
+            _ => continue,
+        };
+        found = true;
+
+        // git normalizes section and variable names to lowercase, subsections keep their case
+        let key = &name["gitsite.".len()..];
+        match key.split_once('.') {
+            None if key == "defaultbranch" => config.repo.default_branch = Some(value),
+            None if key == "description" => config.repo.description = Some(value),
+            None if key == "readme" => config.repo.readme = Some(PathBuf::from(value)),
+            Some((subsection, lang)) if subsection.eq_ignore_ascii_case("langOverride") => {
+                config.lang_override.insert(lang.to_owned(), value);
+            }
+            Some((subsection, key)) if subsection.eq_ignore_ascii_case("access") => {
+                access.entry(key.to_owned())
+                    .or_default()
+                    .push(value);
+            }
+            _ => log::warn!("ignoring unknown git config key {:?} in {:?}", name, repo_path),
+        }
+    }
+
+    if !access.is_empty() {
+        let access = toml::Value::try_from(access)
+            .with_context(|| format!("collecting gitsite.access in git config of {:?}", repo_path))?
+            .try_into()
+            .with_context(|| format!("parsing gitsite.access in git config of {:?}", repo_path))?;
+        config.access = Some(access);
+    }
+
+    Ok(found.then(|| config))
+}
+
+/// Fallback metadata for repositories which don't configure it explicitly
+///
+/// - description from `gitweb.description` or the `description` file
+/// - default branch from the branch `HEAD` points to
+async fn read_git_metadata(repo_path: &Path) -> config::Repo {
+    // `git2::Config` isn't `Send` so it must not be held across an await point
+    let gitweb_description = git2::Config::open(&repo_path.join("config"))
+        .and_then(|git_config| git_config.get_string("gitweb.description"))
+        .ok();
+
+    let description = match gitweb_description {
+        Some(description) => Some(description),
+        None => fs::read_to_string(repo_path.join("description")).await
+            .ok()
+            .map(|description| description.trim().to_owned())
+            .filter(|description| !description.is_empty() && description != DEFAULT_DESCRIPTION),
+    };
+
+    let default_branch = fs::read_to_string(repo_path.join("HEAD")).await
+        .ok()
+        .map(|head| {
+            let head = head.trim();
+            // HEAD is either a symbolic ref or a detached commit id
+            head.strip_prefix("ref: ")
+                .map(|head| head.strip_prefix("refs/heads/").unwrap_or(head))
+                .unwrap_or(head)
+                .to_owned()
+        });
+
+    config::Repo {
+        default_branch,
+        description,
+        readme: None,
+    }
+}
+
 #[rocket::async_trait]
 impl<'req> FromRequest<'req> for Repo {
     type Error = crate::error::Error;
 
     async fn from_request(request: &'req Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
         let config = request.guard::<&State<crate::Config>>().await.unwrap();
+        let cache = request.guard::<&State<RepoCache>>().await.unwrap();
 
-        let repo_name = match request.param::<&str>(0) {
+        let repo_name = match request.param::<RepoName>(0) {
             Some(Ok(repo_name)) => repo_name,
-            Some(Err(_)) => unreachable!(), // parsing &str never fails
-            None => return Outcome::Forward(()),
+            Some(Err(_)) | None => return Outcome::Forward(()),
         };
 
-        let repo_path = config.git_root.join(&repo_name);
-
-        let res = Repo::open(&repo_path, &repo_name).await;
+        let res = cache.repo(config, &repo_name).await;
         match res {
             Ok(Some(repo)) => Outcome::Success(repo),
             Ok(None) => Outcome::Forward(()),
@@ -0,0 +1,204 @@
+use crate::api;
+use crate::Config;
+use rocket::data::Data;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::fmt::{FromUriParam, Path as UriPath};
+use rocket::http::uri::Origin;
+use rocket::request::{FromParam, Request};
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+
+/// Name of a repository relative to `git_root`, possibly nested like `group/subgroup/repo`
+///
+/// Inside a route the whole name is a single segment with the slashes percent-encoded,
+/// [`NestedRepoNames`] takes care of rewriting the unencoded request paths to that form.
+pub struct RepoName {
+    name: String,
+}
+
+#[derive(Error, Debug)]
+pub enum RepoNameError {
+    #[error("empty repository name component")]
+    Empty,
+
+    #[error("forbidden repository name component {0:?}")]
+    BadComponent(&'static str),
+}
+
+impl<'a> FromParam<'a> for RepoName {
+    type Error = RepoNameError;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        for component in param.split('/') {
+            match component {
+                "" => return Err(RepoNameError::Empty),
+                "." => return Err(RepoNameError::BadComponent(".")),
+                ".." => return Err(RepoNameError::BadComponent("..")),
+                _ => {}
+            }
+        }
+
+        Ok(RepoName { name: param.to_owned() })
+    }
+}
+
+// the name is always displayed unencoded with the slashes so that the generated links match the
+// request paths `git` uses when cloning
+
+impl<'a> FromUriParam<UriPath, &'a Path> for RepoName {
+    type Target = &'a Path;
+
+    fn from_uri_param(param: &'a Path) -> &'a Path {
+        param
+    }
+}
+
+impl<'a> FromUriParam<UriPath, &'a str> for RepoName {
+    type Target = &'a Path;
+
+    fn from_uri_param(param: &'a str) -> &'a Path {
+        Path::new(param)
+    }
+}
+
+impl<'a> FromUriParam<UriPath, &'a String> for RepoName {
+    type Target = &'a Path;
+
+    fn from_uri_param(param: &'a String) -> &'a Path {
+        Path::new(param)
+    }
+}
+
+impl Deref for RepoName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.name
+    }
+}
+
+impl AsRef<Path> for RepoName {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.name)
+    }
+}
+
+impl fmt::Debug for RepoName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+
+/// Check whether `path` looks like a bare git repository
+pub async fn is_git_dir(path: &Path) -> bool {
+    let head = tokio::fs::metadata(path.join("HEAD")).await;
+    let objects = tokio::fs::metadata(path.join("objects")).await;
+    matches!((head, objects), (Ok(head), Ok(objects)) if head.is_file() && objects.is_dir())
+}
+
+
+/// Request fairing which rewrites `/group/repo/...` into `/group%2Frepo/...` so that the nested
+/// repository name can be matched by a single `<repo_name>` segment
+pub struct NestedRepoNames;
+
+/// Mount points whose first segment after the base is a repository name
+const REPO_BASES: &[&str] = &[api::BASE, "/"];
+
+/// First segments of routes mounted at `/` which never name a repository, requests for them are
+/// passed through without looking at the disk
+const TOP_LEVEL_ROUTES: &[&str] = &["api", "static", "search", "pushes.atom", "favicon.ico"];
+
+#[rocket::async_trait]
+impl Fairing for NestedRepoNames {
+    fn info(&self) -> Info {
+        Info {
+            name: "Nested repository names",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let config = match request.rocket().state::<Config>() {
+            Some(config) => config,
+            None => return,
+        };
+
+        // collect owned copies, the request URI is replaced below
+        let raw_segments = request.uri().path()
+            .raw_segments()
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| segment.as_str().to_owned())
+            .collect::<Vec<_>>();
+        let segments = request.uri().path()
+            .segments()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let query = request.uri().query()
+            .map(|query| query.raw().as_str().to_owned());
+
+        for base in REPO_BASES {
+            let base = base.split('/')
+                .filter(|segment| !segment.is_empty())
+                .collect::<Vec<_>>();
+            let matches_base = segments.len() >= base.len() &&
+                segments.iter().zip(&base).all(|(segment, base)| segment == base);
+            if !matches_base {
+                continue;
+            }
+
+            let start = base.len();
+            if start == 0 && segments.first().map_or(false, |segment| TOP_LEVEL_ROUTES.contains(&segment.as_str())) {
+                continue;
+            }
+            let len = match nested_repo_len(&config.git_root, config.repo_depth, &segments[start..]).await {
+                Some(len) => len,
+                None => continue,
+            };
+            let end = start + len;
+
+            let mut path = String::new();
+            for segment in &raw_segments[..start] {
+                path.push('/');
+                path.push_str(segment);
+            }
+            path.push('/');
+            path.push_str(&raw_segments[start..end].join("%2F"));
+            for segment in &raw_segments[end..] {
+                path.push('/');
+                path.push_str(segment);
+            }
+            if let Some(query) = &query {
+                path.push('?');
+                path.push_str(query);
+            }
+
+            match Origin::parse_owned(path) {
+                Ok(uri) => request.set_uri(uri),
+                Err(err) => log::warn!("failed to rewrite nested repository path: {}", err),
+            }
+            return;
+        }
+    }
+}
+
+/// Number of leading `segments` which name a nested repository, top level repositories don't
+/// need rewriting and are not considered
+async fn nested_repo_len(git_root: &Path, max_depth: usize, segments: &[String]) -> Option<usize> {
+    let mut path = PathBuf::from(git_root);
+    for (idx, segment) in segments.iter().take(max_depth).enumerate() {
+        if RepoName::from_param(segment).is_err() || segment.contains('/') {
+            return None;
+        }
+        path.push(segment);
+
+        // repositories can't be nested inside other repositories, the first match wins
+        if is_git_dir(&path).await {
+            return (idx > 0).then(|| idx + 1);
+        }
+    }
+    None
+}
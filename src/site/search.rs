@@ -0,0 +1,317 @@
+//! Searching blob contents of a single revision
+
+use crate::error::Result;
+use crate::git_repo::{GitRepo, Object};
+use crate::nav::Nav;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::view::View;
+use crate::Config;
+use glob::Pattern;
+use regex::{Regex, RegexBuilder};
+use rocket::http::uri::Origin;
+use rocket::{get, routes, uri, Route, State};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+
+/// Default number of lines shown around each match
+const DEFAULT_CONTEXT: usize = 2;
+
+/// Maximum number of context lines which can be requested
+const MAX_CONTEXT: usize = 10;
+
+/// Lines longer than this are cut in the results
+const MAX_LINE_LENGTH: usize = 500;
+
+/// Limits of a single search, whichever is reached first ends the search
+pub struct Budget {
+    deadline: Instant,
+    bytes: usize,
+    matches: usize,
+}
+
+impl Budget {
+    pub fn new(config: &Config) -> Budget {
+        Budget {
+            deadline: Instant::now() + Duration::from_millis(config.search_timeout_ms),
+            bytes: config.search_max_bytes,
+            matches: config.search_max_matches,
+        }
+    }
+
+    /// Account for searching `bytes`, returns why the search has to stop if the budget ran out
+    pub fn spend(&mut self, bytes: usize) -> Option<&'static str> {
+        if Instant::now() > self.deadline {
+            return Some("time limit");
+        }
+        match self.bytes.checked_sub(bytes) {
+            Some(left) => self.bytes = left,
+            None => return Some("size limit"),
+        }
+        (self.matches == 0).then(|| "match limit")
+    }
+
+    /// Number of matching lines which can still be found
+    pub fn matches_left(&self) -> usize {
+        self.matches
+    }
+
+    pub fn found(&mut self, matches: usize) {
+        self.matches = self.matches.saturating_sub(matches);
+    }
+}
+
+/// How the query is interpreted
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Literal,
+    Regex,
+}
+
+impl Mode {
+    pub fn parse(mode: Option<&str>) -> Mode {
+        match mode {
+            Some("regex") => Mode::Regex,
+            _ => Mode::Literal,
+        }
+    }
+}
+
+/// Case sensitivity, `Smart` is case insensitive unless the query contains an uppercase letter
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+impl Case {
+    pub fn parse(case: Option<&str>) -> Case {
+        match case {
+            Some("sensitive") => Case::Sensitive,
+            Some("insensitive") => Case::Insensitive,
+            _ => Case::Smart,
+        }
+    }
+
+    /// Whether `query` is matched case insensitively
+    pub fn is_insensitive(self, query: &str) -> bool {
+        match self {
+            Case::Smart => !query.chars().any(char::is_uppercase),
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+        }
+    }
+}
+
+/// Build the regex matching `query`, the error is meant to be shown to the user
+pub fn matcher(query: &str, mode: Mode, case: Case) -> std::result::Result<Regex, String> {
+    let pattern = match mode {
+        Mode::Literal => regex::escape(query),
+        Mode::Regex => query.to_owned(),
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(case.is_insensitive(query))
+        .multi_line(true)
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+#[derive(Serialize)]
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub href: Origin<'static>,
+    /// Groups of consecutive lines, each with at least one match
+    pub hunks: Vec<Vec<Line>>,
+}
+
+#[derive(Serialize)]
+pub struct Line {
+    /// Line number starting at 1
+    pub number: usize,
+    pub href: String,
+    pub is_match: bool,
+    /// The line split into alternating unmatched and matched parts
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+pub struct Part {
+    pub text: String,
+    pub is_match: bool,
+}
+
+/// Find the lines of `text` matching `regex` and group them into hunks with `context` lines
+///
+/// `file_href` is the link to the file, lines link to its `#L<n>` anchors. Returns the hunks and
+/// the number of matching lines.
+pub fn match_lines(text: &str, regex: &Regex, context: usize, file_href: &str, max_matches: usize) -> (Vec<Vec<Line>>, usize) {
+    let lines = text.lines().collect::<Vec<_>>();
+    let matching = lines.iter()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(i, _)| i)
+        .take(max_matches)
+        .collect::<Vec<_>>();
+
+    let mut hunks = Vec::<Vec<Line>>::new();
+    let mut hunk_end = 0;
+
+    for &i in &matching {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+
+        // overlapping or adjacent ranges are merged into the previous hunk
+        let start = match hunks.last() {
+            Some(_) if start <= hunk_end => hunk_end,
+            _ => {
+                hunks.push(Vec::new());
+                start
+            }
+        };
+
+        let hunk = hunks.last_mut().unwrap();
+        for number in start..end.max(start) {
+            hunk.push(line(lines[number], number + 1, regex, file_href));
+        }
+        hunk_end = hunk_end.max(end);
+    }
+
+    (hunks, matching.len())
+}
+
+fn line(text: &str, number: usize, regex: &Regex, file_href: &str) -> Line {
+    let text = match text.char_indices().nth(MAX_LINE_LENGTH) {
+        Some((cut, _)) => &text[..cut],
+        None => text,
+    };
+
+    let mut parts = Vec::new();
+    let mut last = 0;
+    for found in regex.find_iter(text).filter(|found| !found.as_str().is_empty()) {
+        parts.push(Part { text: text[last..found.start()].to_owned(), is_match: false });
+        parts.push(Part { text: found.as_str().to_owned(), is_match: true });
+        last = found.end();
+    }
+    parts.push(Part { text: text[last..].to_owned(), is_match: false });
+
+    Line {
+        number,
+        href: format!("{}#L{}", file_href, number),
+        is_match: parts.len() > 1,
+        parts,
+    }
+}
+
+/// Search the blob contents of `refs`
+///
+/// - `q` is the query, either a literal string or a regex depending on `mode`
+/// - `case` is `smart`, `sensitive` or `insensitive`
+/// - `files` is a glob pattern paths have to match
+/// - `context` is the number of lines shown around each match
+#[get("/<_repo_name>/search/<refs>?<q>&<mode>&<case>&<files>&<context>", rank = 2)]
+pub async fn search(
+    _repo_name: RepoName,
+    refs: &str,
+    q: Option<&str>,
+    mode: Option<&str>,
+    case: Option<&str>,
+    files: Option<&str>,
+    context: Option<usize>,
+    repo: Repo,
+    git_repo: GitRepo,
+    nav: Nav,
+    config: &State<Config>,
+) -> Result<Option<View>> {
+    let tree = match git_repo.find_subtree_object_by_path(refs, Path::new(""), None)? {
+        Some(Object::Tree(tree)) => tree,
+        _ => return Ok(None),
+    };
+
+    let query = q.unwrap_or("");
+    let context = context.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT);
+    let form = ctx!{
+        action = uri!(search(Path::new(&repo.name), refs, _, _, _, _, _)),
+        q = query,
+        mode = mode.unwrap_or("literal"),
+        case = case.unwrap_or("smart"),
+        files = files.unwrap_or(""),
+        context,
+    };
+
+    let regex = match matcher(query, Mode::parse(mode), Case::parse(case)) {
+        Ok(regex) => regex,
+        Err(err) => return Ok(Some(render(repo, nav, form, ctx!{ error = err }))),
+    };
+    let pattern = match files.filter(|files| !files.is_empty()).map(Pattern::new).transpose() {
+        Ok(pattern) => pattern,
+        Err(err) => return Ok(Some(render(repo, nav, form, ctx!{ error = format!("invalid file pattern: {}", err) }))),
+    };
+    if query.is_empty() {
+        return Ok(Some(render(repo, nav, form, serde_json::Value::Null)));
+    }
+
+    let paths = git_repo.tree_blobs(&tree)?
+        .into_iter()
+        .filter(|(path, _)| pattern.as_ref().map_or(true, |pattern| pattern.matches_path(path)));
+
+    let mut budget = Budget::new(config);
+    let mut results = Vec::new();
+    let mut searched = 0;
+    let mut matches = 0;
+    let mut stopped = None;
+
+    for (path, oid) in paths {
+        // big blobs are skipped without being loaded
+        if git_repo.blob_size(oid)? > config.max_blob_size {
+            continue;
+        }
+        let blob = git_repo.find_blob(oid)?;
+        if blob.is_binary() {
+            continue;
+        }
+
+        stopped = budget.spend(blob.size());
+        if stopped.is_some() {
+            break;
+        }
+        searched += 1;
+
+        let href = uri!(crate::web::tree(Path::new(&repo.name), refs, &path, _));
+        let text = String::from_utf8_lossy(blob.content());
+        let (hunks, found) = match_lines(&text, &regex, context, &href.to_string(), budget.matches_left());
+        if found == 0 {
+            continue;
+        }
+
+        budget.found(found);
+        matches += found;
+        results.push(FileMatches { path, href, hunks });
+    }
+
+    Ok(Some(render(repo, nav, form, ctx!{
+        files = results,
+        searched,
+        matches,
+        stopped,
+    })))
+}
+
+fn render(repo: Repo, nav: Nav, form: serde_json::Value, results: serde_json::Value) -> View {
+    View::render("search", ctx!{
+        repo,
+        form,
+        results,
+        nav,
+        view = "search",
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        search,
+    }
+}
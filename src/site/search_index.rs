@@ -0,0 +1,444 @@
+//! Persistent trigram index of the default branches of all repositories
+//!
+//! Every file is indexed by the set of lowercased byte trigrams it contains. A literal query can
+//! only match files containing all of its trigrams, so only those are loaded and searched with the
+//! same matcher as the per-repository search. Regex queries can't be narrowed down this way and
+//! scan all indexed files within the search budget.
+//!
+//! Trigrams are stored per blob id, so updating a repository after its default branch moved only
+//! reads the blobs which weren't indexed before.
+
+use crate::cache::RepoCache;
+use crate::error::Result;
+use crate::git_repo::GitRepo;
+use crate::index;
+use crate::nav::Nav;
+use crate::repo::Repo;
+use crate::repo_name::RepoName;
+use crate::repo_path::RepoPath;
+use crate::search::{self, Budget, Case, FileMatches, Mode};
+use crate::view::View;
+use crate::Config;
+use anyhow::Context as _;
+use rocket::fairing::AdHoc;
+use rocket::{get, routes, uri, Route, State};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+
+/// Number of files shown on the result page
+const MAX_RESULTS: usize = 100;
+
+/// Lines of context around matches on the result page
+const CONTEXT: usize = 1;
+
+type Trigram = u32;
+
+/// Index of a single repository
+#[derive(Serialize, Deserialize)]
+struct RepoIndex {
+    repo: String,
+    /// Indexed commit of the default branch
+    commit: String,
+    /// Indexed paths and their blob ids
+    files: Vec<(PathBuf, String)>,
+    /// Sorted trigrams of every indexed blob
+    blobs: HashMap<String, Vec<Trigram>>,
+    /// Indices into `files` containing each trigram, rebuilt when loaded
+    #[serde(skip)]
+    postings: HashMap<Trigram, Vec<u32>>,
+}
+
+impl RepoIndex {
+    fn build_postings(&mut self) {
+        let mut postings = HashMap::<Trigram, Vec<u32>>::new();
+        for (i, (_, blob)) in self.files.iter().enumerate() {
+            for &trigram in self.blobs.get(blob).into_iter().flatten() {
+                postings.entry(trigram).or_default().push(i as u32);
+            }
+        }
+        self.postings = postings;
+    }
+
+    /// Files which may contain all of `trigrams`, all files if there are no trigrams
+    fn candidates(&self, trigrams: &[Trigram]) -> Vec<u32> {
+        if trigrams.is_empty() {
+            return (0..self.files.len() as u32).collect();
+        }
+
+        // start with the rarest trigram to keep the intersection small
+        let mut lists = trigrams.iter()
+            .map(|trigram| self.postings.get(trigram).map(Vec::as_slice).unwrap_or(&[]))
+            .collect::<Vec<_>>();
+        lists.sort_unstable_by_key(|list| list.len());
+
+        let mut candidates = lists[0].to_vec();
+        for list in &lists[1..] {
+            candidates.retain(|i| list.binary_search(i).is_ok());
+        }
+        candidates
+    }
+}
+
+/// Lowercased byte trigrams of `data`, sorted and deduplicated
+fn trigrams(data: &[u8]) -> Vec<Trigram> {
+    let set = data.windows(3)
+        .map(|window| {
+            let byte = |i: usize| window[i].to_ascii_lowercase();
+            u32::from_be_bytes([0, byte(0), byte(1), byte(2)])
+        })
+        .collect::<HashSet<_>>();
+
+    let mut trigrams = set.into_iter().collect::<Vec<_>>();
+    trigrams.sort_unstable();
+    trigrams
+}
+
+/// Trigrams every file matching the literal `query` has to contain
+///
+/// Case insensitive matching folds case by Unicode rules, where non-ASCII letters can match
+/// letters of another UTF-8 length and `k` and `s` match the Kelvin sign and the long s, so
+/// trigrams containing any of those can't be used to rule files out.
+fn query_trigrams(query: &str, case_insensitive: bool) -> Vec<Trigram> {
+    let mut trigrams = trigrams(query.as_bytes());
+    if case_insensitive {
+        trigrams.retain(|trigram| trigram.to_be_bytes()[1..].iter()
+            .all(|&byte| byte.is_ascii() && byte != b'k' && byte != b's'));
+    }
+    trigrams
+}
+
+/// Search index shared by the request handlers and the background updater
+#[derive(Clone, Default)]
+pub struct SearchIndex {
+    repos: Arc<RwLock<HashMap<String, Arc<RepoIndex>>>>,
+}
+
+impl SearchIndex {
+    /// Fairing loading the persisted index and keeping it up to date in the background
+    ///
+    /// Does nothing unless `search_index_dir` is configured.
+    pub fn fairing() -> AdHoc {
+        AdHoc::on_liftoff("Search index", |rocket| Box::pin(async move {
+            let config = rocket.state::<Config>().unwrap().clone();
+            let search_index = rocket.state::<SearchIndex>().unwrap().clone();
+            let dir = match &config.search_index_dir {
+                Some(dir) => dir.clone(),
+                None => return,
+            };
+
+            tokio::spawn(async move {
+                if let Err(err) = search_index.load(&dir).await {
+                    log::warn!("{:?}", err);
+                }
+                loop {
+                    if let Err(err) = search_index.update(&config, &dir).await {
+                        log::warn!("{:?}", err);
+                    }
+                    tokio::time::sleep(Duration::from_secs(config.search_index_interval_secs)).await;
+                }
+            });
+        }))
+    }
+
+    /// Load all repository indices stored in `dir`
+    async fn load(&self, dir: &Path) -> anyhow::Result<()> {
+        let dir = dir.to_owned();
+        let loaded = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<RepoIndex>> {
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("creating search index directory {:?}", dir))?;
+
+            let mut loaded = Vec::new();
+            for entry in std::fs::read_dir(&dir).with_context(|| format!("reading directory {:?}", dir))? {
+                let path = entry.context("reading direntry")?.path();
+                if path.extension().map_or(true, |ext| ext != "json") {
+                    continue;
+                }
+                let res = std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| Ok(serde_json::from_slice::<RepoIndex>(&data)?));
+                match res {
+                    Ok(mut repo_index) => {
+                        repo_index.build_postings();
+                        loaded.push(repo_index);
+                    }
+                    Err(err) => log::warn!("ignoring search index file {:?}: {}", path, err),
+                }
+            }
+            Ok(loaded)
+        }).await.context("loading search index")??;
+
+        let mut repos = self.repos.write().unwrap();
+        for repo_index in loaded {
+            repos.insert(repo_index.repo.clone(), Arc::new(repo_index));
+        }
+        Ok(())
+    }
+
+    /// Reindex repositories whose default branch changed and drop the ones which disappeared
+    async fn update(&self, config: &Config, dir: &Path) -> Result<()> {
+        let listing = index::discover(config).await?;
+        let mut present = HashSet::new();
+
+        for name in &listing.names {
+            let repo = match Repo::open(config, name).await {
+                Ok(Some(repo)) => repo,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!("{:?}", err);
+                    continue;
+                }
+            };
+            present.insert(name.clone());
+
+            let old = self.repos.read().unwrap().get(name).cloned();
+            let path = index_path(dir, name);
+            let max_blob_size = config.max_blob_size;
+
+            let res = tokio::task::spawn_blocking(move || {
+                index_repo(&repo, old.as_deref(), max_blob_size, &path)
+            }).await.context("indexing repository")?;
+
+            match res {
+                Ok(Some(repo_index)) => {
+                    log::info!("indexed {} files of {} at {}", repo_index.files.len(), name, repo_index.commit);
+                    self.repos.write().unwrap().insert(name.clone(), Arc::new(repo_index));
+                }
+                Ok(None) => {}
+                Err(err) => log::warn!("{:?}", err),
+            }
+        }
+
+        let removed = self.repos.read().unwrap()
+            .keys()
+            .filter(|name| !present.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in removed {
+            self.repos.write().unwrap().remove(&name);
+            let _ = tokio::fs::remove_file(index_path(dir, &name)).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// File storing the index of `repo_name`, nested names are flattened into a single file name
+fn index_path(dir: &Path, repo_name: &str) -> PathBuf {
+    let file_name = repo_name.replace('%', "%25").replace('/', "%2F");
+    dir.join(format!("{}.json", file_name))
+}
+
+/// Index the default branch of `repo` reusing the trigrams of blobs from `old`
+///
+/// Returns `None` if the default branch didn't move since `old` was built.
+fn index_repo(repo: &Repo, old: Option<&RepoIndex>, max_blob_size: usize, path: &Path) -> Result<Option<RepoIndex>> {
+    let git_repo = GitRepo::open(repo)?;
+    let commit = match git_repo.find_commit(&repo.default_branch)? {
+        Some(commit) => commit,
+        // empty repository
+        None => return Ok(None),
+    };
+    let commit_id = commit.id().to_string();
+    if old.map_or(false, |old| old.commit == commit_id) {
+        return Ok(None);
+    }
+
+    let tree = commit.tree()
+        .with_context(|| format!("finding tree of commit {}", commit_id))?;
+    let mut repo_index = RepoIndex {
+        repo: repo.name.clone(),
+        commit: commit_id,
+        files: Vec::new(),
+        blobs: HashMap::new(),
+        postings: HashMap::new(),
+    };
+
+    for (file_path, oid) in git_repo.tree_blobs(&tree)? {
+        let blob_id = oid.to_string();
+        if !repo_index.blobs.contains_key(&blob_id) {
+            let trigrams = match old.and_then(|old| old.blobs.get(&blob_id)) {
+                Some(trigrams) => trigrams.clone(),
+                None => {
+                    if git_repo.blob_size(oid)? > max_blob_size {
+                        continue;
+                    }
+                    let blob = git_repo.find_blob(oid)?;
+                    if blob.is_binary() {
+                        continue;
+                    }
+                    trigrams(blob.content())
+                }
+            };
+            repo_index.blobs.insert(blob_id.clone(), trigrams);
+        }
+        repo_index.files.push((file_path, blob_id));
+    }
+
+    let data = serde_json::to_vec(&repo_index)
+        .context("serializing search index")?;
+    // write into a temporary file first so that a crash never leaves a truncated index behind
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, data)
+        .and_then(|()| std::fs::rename(&tmp_path, path))
+        .with_context(|| format!("writing search index {:?}", path))?;
+
+    repo_index.build_postings();
+    Ok(Some(repo_index))
+}
+
+#[derive(Serialize)]
+struct RankedMatches {
+    repo: String,
+    repo_href: String,
+    #[serde(flatten)]
+    matches: FileMatches,
+    #[serde(skip)]
+    score: f64,
+}
+
+/// Rank a file by the number of matching lines, where the query appears in the path and the
+/// repository name and how deep the file is nested
+fn score(repo_name: &str, path: &Path, query: &str, matches: usize) -> f64 {
+    let query = query.to_lowercase();
+    let path_str = path.to_string_lossy().to_lowercase();
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let mut score = (matches as f64).ln_1p() * 10.0;
+    if file_name.contains(&query) {
+        score += 20.0;
+    } else if path_str.contains(&query) {
+        score += 10.0;
+    }
+    if repo_name.to_lowercase().contains(&query) {
+        score += 10.0;
+    }
+    score - path.components().count() as f64
+}
+
+/// Search the default branches of all repositories
+#[get("/search?<q>&<mode>&<case>")]
+async fn site_search(
+    q: Option<&str>,
+    mode: Option<&str>,
+    case: Option<&str>,
+    nav: Nav,
+    config: &State<Config>,
+    repo_cache: &State<RepoCache>,
+    search_index: &State<SearchIndex>,
+) -> Result<View> {
+    let query = q.unwrap_or("");
+    let mode = Mode::parse(mode);
+    let form = ctx!{
+        action = uri!(site_search(_, _, _)),
+        q = query,
+        mode = if mode == Mode::Regex { "regex" } else { "literal" },
+        case = case.unwrap_or("smart"),
+    };
+
+    if config.search_index_dir.is_none() {
+        return Ok(render(form, ctx!{ error = "the search index is disabled" }, nav));
+    }
+    let case = Case::parse(case);
+    let regex = match search::matcher(query, mode, case) {
+        Ok(regex) => regex,
+        Err(err) => return Ok(render(form, ctx!{ error = err }, nav)),
+    };
+    if query.is_empty() {
+        return Ok(render(form, serde_json::Value::Null, nav));
+    }
+
+    let query_trigrams = match mode {
+        Mode::Literal => query_trigrams(query, case.is_insensitive(query)),
+        Mode::Regex => Vec::new(),
+    };
+    let mut repo_indices = search_index.repos.read().unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    repo_indices.sort_unstable_by(|a, b| a.repo.cmp(&b.repo));
+
+    let mut budget = Budget::new(config);
+    let mut results = Vec::new();
+    let mut stopped = None;
+
+    'repos: for repo_index in repo_indices {
+        let candidates = repo_index.candidates(&query_trigrams);
+        if candidates.is_empty() {
+            continue;
+        }
+
+        // the repository may have been removed or hidden since it was indexed
+        let repo = match repo_cache.repo(config, &repo_index.repo).await? {
+            Some(repo) => repo,
+            None => continue,
+        };
+        let git_repo = GitRepo::open(&repo)?;
+
+        for i in candidates {
+            let (path, blob_id) = &repo_index.files[i as usize];
+            let oid = git2::Oid::from_str(blob_id)
+                .with_context(|| format!("parsing indexed blob id {:?}", blob_id))?;
+            let blob = match git_repo.find_blob(oid) {
+                Ok(blob) => blob,
+                // the blob may be gone after a force push and gc
+                Err(err) => {
+                    log::debug!("{:?}", err);
+                    continue;
+                }
+            };
+
+            stopped = budget.spend(blob.size());
+            if stopped.is_some() {
+                break 'repos;
+            }
+
+            let href = uri!(crate::web::tree(Path::new(&repo.name), &repo_index.commit, path, _));
+            let text = String::from_utf8_lossy(blob.content());
+            let (hunks, found) = search::match_lines(&text, &regex, CONTEXT, &href.to_string(), budget.matches_left());
+            if found == 0 {
+                continue;
+            }
+            budget.found(found);
+
+            results.push(RankedMatches {
+                repo: repo.name.clone(),
+                repo_href: repo.href.to_string(),
+                score: score(&repo.name, path, query, found),
+                matches: FileMatches { path: path.clone(), href, hunks },
+            });
+        }
+    }
+
+    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap()
+        .then_with(|| a.repo.cmp(&b.repo))
+        .then_with(|| a.matches.path.cmp(&b.matches.path)));
+    let total = results.len();
+    results.truncate(MAX_RESULTS);
+
+    Ok(render(form, ctx!{
+        files = results,
+        total,
+        stopped,
+    }, nav))
+}
+
+fn render(form: serde_json::Value, results: serde_json::Value, nav: Nav) -> View {
+    View::render("site_search", ctx!{
+        form,
+        results,
+        nav,
+        view = "search",
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes! {
+        site_search,
+    }
+}
@@ -0,0 +1,35 @@
+//! Users authenticated by a reverse proxy in front of the site
+
+use crate::Config;
+use rocket::outcome::Outcome;
+use rocket::request::{FromRequest, Request};
+use rocket::State;
+
+
+/// User named by the `user_header` request header
+///
+/// The site doesn't authenticate anyone itself, the header has to be set by a trusted reverse
+/// proxy which also strips it from client requests. The guard forwards if `user_header` isn't
+/// configured or the request doesn't carry it.
+pub struct User {
+    pub name: String,
+}
+
+#[rocket::async_trait]
+impl<'req> FromRequest<'req> for User {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'req Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        let config = request.guard::<&State<Config>>().await.unwrap();
+
+        let name = config.user_header.as_deref()
+            .and_then(|header| request.headers().get_one(header))
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+
+        match name {
+            Some(name) => Outcome::Success(User { name: name.to_owned() }),
+            None => Outcome::Forward(()),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::http_cache::Validators;
+use crate::Config;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::tera::Tera;
+#[cfg(not(feature = "embed"))]
+use rocket_dyn_templates::Template;
+use std::path::{Path, PathBuf};
+
+
+/// Template together with its context which is rendered into HTML or serialized as JSON depending
+/// on the request's `Accept` header
+pub struct View {
+    name: &'static str,
+    ctx: serde_json::Value,
+    validators: Option<Validators>,
+}
+
+impl View {
+    pub fn render(name: &'static str, ctx: serde_json::Value) -> View {
+        View { name, ctx, validators: None }
+    }
+
+    /// Send `ETag` and `Cache-Control` and honor `If-None-Match`
+    pub fn cached(mut self, validators: Validators) -> View {
+        self.validators = Some(validators);
+        self
+    }
+}
+
+/// Template rendered into HTML, or into XML with an explicit content type
+///
+/// Templates are rendered by `rocket_dyn_templates` from `template_dir` or from memory with the
+/// `embed` feature.
+pub struct Page {
+    name: &'static str,
+    ctx: serde_json::Value,
+}
+
+impl Page {
+    pub fn render(name: &'static str, ctx: serde_json::Value) -> Page {
+        Page { name, ctx }
+    }
+}
+
+impl<'req> Responder<'req, 'static> for Page {
+    #[cfg(not(feature = "embed"))]
+    fn respond_to(self, request: &'req Request<'_>) -> response::Result<'static> {
+        Template::render(self.name, self.ctx).respond_to(request)
+    }
+
+    #[cfg(feature = "embed")]
+    fn respond_to(self, request: &'req Request<'_>) -> response::Result<'static> {
+        let templates = request.rocket().state::<crate::assets::Templates>()
+            .ok_or(rocket::http::Status::InternalServerError)?;
+
+        match templates.render(self.name, &self.ctx) {
+            Ok(html) => (rocket::http::ContentType::HTML, html).respond_to(request),
+            Err(err) => {
+                log::error!("rendering template {:?}: {:?}", self.name, err);
+                Err(rocket::http::Status::InternalServerError)
+            }
+        }
+    }
+}
+
+impl<'req> Responder<'req, 'static> for View {
+    fn respond_to(self, request: &'req Request<'_>) -> response::Result<'static> {
+        let wants_json = request.accept()
+            .map(|accept| accept.preferred().media_type().is_json())
+            .unwrap_or(false);
+
+        let View { name, mut ctx, validators } = self;
+        let render = || if wants_json {
+            Json(ctx).respond_to(request)
+        } else {
+            // branding is only needed by the templates, it's left out of the JSON
+            if let Some(config) = request.rocket().state::<Config>() {
+                ctx["site"] = ctx!{
+                    name = config.site_name,
+                    footer = config.footer,
+                    logo = config.logo,
+                };
+            }
+            Page::render(name, ctx).respond_to(request)
+        };
+
+        let response = match validators {
+            Some(validators) => {
+                let variant = if wants_json { "json" } else { "html" };
+                validators.respond(request, variant, render)?
+            }
+            None => render()?,
+        };
+
+        // caches must not serve the JSON variant to browsers and vice versa
+        Response::build_from(response)
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
+
+/// Replace the built-in templates with the ones of the same name in `dir`
+///
+/// Templates are named the way `rocket_dyn_templates` names them, by their path relative to the
+/// directory without the `.tera` and the format extension, so `dir/fragments/repo_nav.html.tera`
+/// shadows `fragments/repo_nav`. Templates which don't exist built-in are added. Errors are only
+/// logged, the built-in templates are used instead.
+pub fn load_template_overrides(tera: &mut Tera, dir: &Path) {
+    let mut files = Vec::new();
+    if let Err(err) = find_templates(dir, &mut files) {
+        log::error!("reading template override directory {:?}: {}", dir, err);
+        return;
+    }
+
+    let templates = files.into_iter()
+        .filter_map(|path| {
+            let name = path.strip_prefix(dir).ok()?
+                .to_string_lossy()
+                .strip_suffix(".tera")?
+                .to_owned();
+            // strip the format extension of the file name only, directories may contain dots
+            let name = match name.rsplit_once('.') {
+                Some((stem, ext)) if !ext.contains('/') && !stem.is_empty() && !stem.ends_with('/') => stem.to_owned(),
+                _ => name,
+            };
+            Some((path, Some(name)))
+        })
+        .collect::<Vec<_>>();
+
+    for (path, name) in &templates {
+        log::info!("overriding template {:?} with {:?}", name.as_deref().unwrap_or_default(), path);
+    }
+    if let Err(err) = tera.add_template_files(templates) {
+        log::error!("loading template overrides from {:?}: {:?}", dir, err);
+    }
+}
+
+fn find_templates(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_templates(&path, files)?;
+        } else if path.extension().map_or(false, |ext| ext == "tera") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
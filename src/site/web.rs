@@ -1,19 +1,44 @@
+use crate::assets;
+use crate::cache::RepoCache;
 use crate::error::Result;
-use crate::git_repo::{self, GitRepo, Object};
+use crate::git_repo::{self, GitRepo, LargeBlob, Object, TreeSummary};
+use crate::hexdump::ByteOffset;
+use crate::http_cache::{Cached, Validators};
 use crate::index::Index;
+use crate::media;
 use crate::nav::Nav;
-use crate::repo::{File, FileMode, Repo};
+use crate::render_cache::RenderCache;
+use crate::repo::{File, FileMode, Repo, Submodule, Symlink};
+use crate::repo_name::RepoName;
 use crate::repo_path::RepoPath;
+use crate::user::User;
+use crate::view::View;
 use crate::Config;
 use anyhow::Context as _;
 use rocket::fs::NamedFile;
-use rocket::http::Status;
-use rocket::response::Redirect;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Redirect, Responder, Response};
 use rocket::{get, routes, uri, Route, State};
-use rocket_dyn_templates::Template;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+
+/// Number of the largest files listed in the repository summary
+const SUMMARY_LARGEST_FILES: usize = 10;
+
+/// Number of commits of the current ref offered by the ref switcher
+const REFS_RECENT_COMMITS: usize = 20;
+
+/// Number of the newest matching tags offered by the ref switcher, older ones can be found with `q`
+const REFS_MAX_TAGS: usize = 50;
+
+/// Size of the chunks raw blobs are streamed in
+const RAW_CHUNK_SIZE: usize = 64 * 1024;
 
 
 #[get("/favicon.ico")]
@@ -21,62 +46,247 @@ async fn favicon() -> Status {
     Status::NoContent
 }
 
+/// Files in `static_dir` take precedence over the embedded ones
 #[get("/static/<path..>")]
-async fn statics(path: PathBuf, config: &State<Config>) -> Option<NamedFile> {
-    let path = config.static_dir.join(path);
-    let res = NamedFile::open(&path).await;
+async fn statics(path: PathBuf, config: &State<Config>) -> Option<assets::Static> {
+    let file_path = config.static_dir.join(&path);
+    let res = NamedFile::open(&file_path).await;
     match res {
-        Ok(file) => Some(file),
+        Ok(file) => Some(assets::Static::File(file)),
         Err(_) => {
-            let err = res.context(format!("reading static file {:?}", &path));
-            log::warn!("{:?}", err.unwrap_err());
-            None
+            let embedded = path.to_str().and_then(assets::find);
+            if embedded.is_none() {
+                let err = res.context(format!("reading static file {:?}", &file_path));
+                log::warn!("{:?}", err.unwrap_err());
+            }
+            embedded
         }
     }
 }
 
+#[derive(Serialize)]
+struct Group {
+    /// Directory containing the repositories relative to `git_root`
+    name: String,
+    repos: Vec<Repo>,
+}
+
 #[get("/")]
-async fn index(index: Index, nav: Nav) -> Result<Template> {
+async fn index(index: Index, nav: Nav) -> Result<View> {
     let Index { mut repos, .. } = index;
 
-    repos.sort_unstable_by_key(|repo| repo.name.to_ascii_lowercase());
+    repos.sort_unstable_by_key(|repo| (
+        repo.group().to_ascii_lowercase(),
+        repo.name.to_ascii_lowercase(),
+    ));
+
+    // repos are sorted by group first so each group is a contiguous run
+    let mut groups = Vec::<Group>::new();
+    for repo in repos {
+        match groups.last_mut() {
+            Some(group) if group.name == repo.group() => group.repos.push(repo),
+            _ => groups.push(Group {
+                name: repo.group().to_owned(),
+                repos: vec![repo],
+            }),
+        }
+    }
 
-    Ok(Template::render("index", ctx!{
-        repos,
+    Ok(View::render("index", ctx!{
+        groups,
         nav,
         view = "index",
     }))
 }
 
 #[get("/<_repo_name>", rank = 2)]
-pub async fn home(_repo_name: &str, repo: Repo) -> Result<Redirect> {
-    Ok(Redirect::to(uri!(tree(Path::new(&repo.name), &repo.default_branch, Path::new("/")))))
+pub async fn home(_repo_name: RepoName, repo: Repo) -> Result<Redirect> {
+    Ok(Redirect::to(uri!(tree(Path::new(&repo.name), &repo.default_branch, Path::new("/"), _))))
 }
 
-#[get("/<_repo_name>/tree/<refs>/<path..>", rank = 2)]
-pub async fn tree(_repo_name: PathBuf, refs: &str, path: RepoPath, repo: Repo, git_repo: GitRepo, nav: Nav) -> Result<Template> {
-    let object = git_repo.find_subtree_object_by_path(&refs, &path)
+/// `offset` selects the hexdump page of binary files
+#[get("/<_repo_name>/tree/<refs>/<path..>?<offset>", rank = 2)]
+pub async fn tree(_repo_name: RepoName, refs: &str, path: RepoPath, offset: Option<usize>, repo: Repo, git_repo: GitRepo, nav: Nav, user: Option<User>, config: &State<Config>, render_cache: &State<RenderCache>, repo_cache: &State<RepoCache>) -> Result<View> {
+    // git objects can't be held across awaits so submodules and the summary are resolved first
+    let submodules = Submodules::read(refs, &git_repo);
+    let submodules = submodules.resolve(&repo, config, repo_cache).await;
+    let summary = if path.as_os_str().is_empty() {
+        let root = match git_repo.find_subtree_object_by_path(&refs, Path::new(""), None)? {
+            Some(Object::Tree(tree)) => Some(tree.id()),
+            _ => None,
+        };
+        match root {
+            Some(root) => tree_summary(root, &repo, render_cache).await,
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    let object = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_blob_size))
         .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))?
         .context("404")?;
 
     match object {
-        git_repo::Object::Tree(tree) => render_ls_files(tree, &refs, &path, repo, &git_repo, nav),
-        git_repo::Object::Blob(blob) => render_blob(blob, &path, repo, nav),
+        git_repo::Object::Tree(tree) => {
+            // push URLs are only shown on the repository root
+            let can_push = path.as_os_str().is_empty() && repo.can_push(user.as_ref());
+            let clone = path.as_os_str().is_empty().then(|| clone_urls(&repo, config, user.as_ref()));
+            let validators = Validators::new(tree.id(), refs, &repo, config)
+                .personalized(can_push)
+                .depends_on((submodules.key(&tree), clone.as_ref().map(|clone| clone.to_string())));
+            let view = render_ls_files(tree, &refs, &path, &submodules, summary, clone, repo, &git_repo, nav, config, render_cache)?;
+            Ok(view.cached(validators))
+        }
+        git_repo::Object::Blob(blob) => {
+            let validators = Validators::new(blob.id(), refs, &repo, config);
+            let view = render_blob(blob, refs, &path, offset.unwrap_or(0), repo, nav, config, render_cache)?;
+            Ok(view.cached(validators))
+        }
+        git_repo::Object::LargeBlob(blob) => {
+            let validators = Validators::new(blob.id, refs, &repo, config);
+            let view = render_large_blob(&blob, refs, &path, repo, nav)?;
+            Ok(view.cached(validators))
+        }
     }
 }
 
-#[get("/<_repo_name>/refs/<_refs>/<path..>", rank = 2)]
-pub async fn refs(_repo_name: &str, _refs: &str, path: RepoPath, repo: Repo, git_repo: GitRepo, nav: Nav) -> Result<Template> {
+/// Blob content as is, text is always served as `text/plain` so that repository content can't
+/// inject scripts into the site
+///
+/// Blobs over `max_blob_size` are streamed and their content type is guessed from the extension.
+#[get("/<_repo_name>/raw/<refs>/<path..>", rank = 2)]
+pub async fn raw(_repo_name: RepoName, refs: &str, path: RepoPath, repo: Repo, git_repo: GitRepo, config: &State<Config>) -> Result<Option<Cached<RawBlob>>> {
+    let object = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_blob_size))
+        .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))?;
+
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let by_extension = || path.extension()
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()));
+
+    let (id, content_type) = match object {
+        Some(Object::Blob(blob)) if blob.is_binary() => {
+            let content_type = media::detect(&name, blob.content())
+                .and_then(|media| ContentType::parse_flexible(media.mime))
+                .or_else(by_extension);
+            (blob.id(), content_type)
+        }
+        Some(Object::Blob(blob)) => (blob.id(), Some(ContentType::Plain)),
+        Some(Object::LargeBlob(blob)) => (blob.id, by_extension()),
+        _ => return Ok(None),
+    };
+    let content_type = content_type
+        .filter(|content_type| !content_type.is_html() && !content_type.is_javascript())
+        .unwrap_or(ContentType::Binary);
+
+    Ok(Some(Cached {
+        validators: Validators::new(id, refs, &repo, config),
+        inner: RawBlob {
+            content_type,
+            content: stream_blob(repo, id),
+        },
+    }))
+}
+
+/// Blob content streamed by [`stream_blob`]
+pub struct RawBlob {
+    content_type: ContentType,
+    content: DuplexStream,
+}
+
+impl<'r> Responder<'r, 'static> for RawBlob {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            // browsers mustn't second-guess the content type and render blobs as HTML
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .streamed_body(self.content)
+            .ok()
+    }
+}
+
+/// Read the blob `id` on a blocking thread, the returned stream ends early if reading fails
+fn stream_blob(repo: Repo, id: git2::Oid) -> DuplexStream {
+    let (mut writer, reader) = tokio::io::duplex(RAW_CHUNK_SIZE);
+    let runtime = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let res = GitRepo::open(&repo).and_then(|git_repo| {
+            git_repo.write_blob(id, RAW_CHUNK_SIZE, |chunk| runtime.block_on(writer.write_all(chunk)))
+        });
+        if let Err(err) = res {
+            log::warn!("{:?}", err);
+        }
+    });
+
+    reader
+}
+
+/// Switch to another branch, tag or recent commit while keeping the current path
+///
+/// Refs where the path doesn't exist are marked and link to its closest existing ancestor instead.
+/// `q` filters the refs by name, the page also filters them as you type.
+#[get("/<_repo_name>/refs/<refs>/<path..>?<q>", rank = 2)]
+pub async fn refs(_repo_name: RepoName, refs: &str, path: RepoPath, q: Option<&str>, repo: Repo, git_repo: GitRepo, nav: Nav) -> Result<View> {
+    let filter = q.unwrap_or("").trim().to_lowercase();
+    let matches = |name: &str| name.to_lowercase().contains(&filter);
+
+    // refs are resolved by their full name so that a branch isn't mistaken for a tag or an
+    // abbreviated commit id of the same name
+    let switch = |name: &str, full_name: &str| -> Result<Option<serde_json::Value>> {
+        let existing = match git_repo.closest_existing_path(full_name, &path)? {
+            Some(existing) => existing,
+            None => return Ok(None),
+        };
+        Ok(Some(ctx!{
+            href = uri!(tree(Path::new(&repo.name), name, &existing, _)),
+            name,
+            current = name == refs,
+            has_path = existing == *path,
+        }))
+    };
+
     let branches = git_repo.branches()?
-        .into_iter()
-        .map(|branch| ctx! {
-            href = uri!(tree(Path::new(&repo.name), &branch.name, &path)),
-            name = branch.name,
-        })
-        .collect::<Vec<_>>();
+        .iter()
+        .filter(|branch| matches(&branch.name))
+        .filter_map(|branch| switch(&branch.name, &format!("refs/heads/{}", branch.name)).transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut tags = git_repo.tags()?;
+    tags.sort_unstable_by(|a, b| b.tagger.time.cmp(&a.tagger.time));
+    tags.retain(|tag| matches(&tag.name));
+    let more_tags = tags.len().saturating_sub(REFS_MAX_TAGS);
+    let tags = tags.iter()
+        .take(REFS_MAX_TAGS)
+        .filter_map(|tag| switch(&tag.name, &format!("refs/tags/{}", tag.name))
+            .map(|ctx| ctx.map(|ctx| ctx!{ switch = ctx, tag }))
+            .transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    let commits = match git_repo.find_commit(refs)? {
+        Some(commit) => git_repo.log(&commit, 0, REFS_RECENT_COMMITS)?
+            .iter()
+            .filter(|commit| matches(&commit.id) || matches(&commit.summary))
+            .filter_map(|commit| switch(&commit.id, &commit.id)
+                .map(|ctx| ctx.map(|ctx| ctx!{ switch = ctx, commit }))
+                .transpose())
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
 
-    Ok(Template::render("refs", ctx!{
+    Ok(View::render("refs", ctx!{
+        repo,
+        path = &*path,
         branches,
+        tags,
+        more_tags,
+        commits,
+        form = ctx!{
+            action = uri!(refs(Path::new(&repo.name), refs, &*path, _)),
+            q = q.unwrap_or(""),
+        },
         nav,
         view = "refs",
     }))
@@ -89,19 +299,111 @@ pub fn routes() -> Vec<Route> {
         index,
         home,
         tree,
+        raw,
         refs,
     }
 }
 
 
-fn render_ls_files(tree: git2::Tree<'_>, refs: &str, path: &Path, repo: Repo, git_repo: &GitRepo, nav: Nav) -> Result<Template> {
+/// `summary` and `clone` are the [`tree_summary`] and the [`clone_urls`] shown on the repository
+/// root
+fn render_ls_files(tree: git2::Tree<'_>, refs: &str, path: &Path, submodules: &Submodules, summary: Option<Arc<TreeSummary>>, clone: Option<serde_json::Value>, repo: Repo, git_repo: &GitRepo, nav: Nav, config: &Config, render_cache: &RenderCache) -> Result<View> {
+    // entries link to `refs` so the listing depends on it and not only on the tree
+    let options = format!("{}\0{}\0{}{}", repo.name, refs, path.display(), submodules.key(&tree));
+    let files = render_cache.get_or_render("tree", tree.id(), &options, || {
+        ls_files(&tree, refs, path, &repo, git_repo, submodules)
+    });
+    let readme = render_readme(&refs, &files, &repo, &git_repo, config, render_cache);
+
+    let summary = summary.map(|summary| ctx!{
+        files = summary.files,
+        size = summary.size,
+        largest = summary.largest.iter()
+            .map(|file| ctx!{
+                path = file.path,
+                size = file.size,
+                href = uri!(tree(Path::new(&repo.name), refs, &file.path, _)),
+            })
+            .collect::<Vec<_>>(),
+    });
+    Ok(View::render("tree", ctx!{
+        repo,
+        files = *files,
+        readme = readme.as_deref(),
+        summary,
+        clone,
+        nav,
+        view = "tree",
+    }))
+}
+
+/// Summary of the tree `oid`, the summary covers the whole tree so it's only shown at the
+/// repository root
+///
+/// Walking a large tree takes a while so it's done on a blocking thread, failures aren't cached.
+async fn tree_summary(oid: git2::Oid, repo: &Repo, render_cache: &RenderCache) -> Option<Arc<TreeSummary>> {
+    if let Some(summary) = render_cache.get("summary", oid, "") {
+        return Some(summary);
+    }
+
+    let repo = repo.clone();
+    let res = tokio::task::spawn_blocking(move || -> Result<TreeSummary> {
+        GitRepo::open(&repo)?.tree_summary(oid, SUMMARY_LARGEST_FILES)
+    }).await;
+    match res {
+        Ok(Ok(summary)) => Some(render_cache.get_or_render("summary", oid, "", || summary)),
+        Ok(Err(err)) => {
+            log::warn!("{:?}", err);
+            None
+        }
+        Err(err) => {
+            log::warn!("summarizing tree {}: {}", oid, err);
+            None
+        }
+    }
+}
+
+/// Clone URLs of the repository, the push URL is only included for users allowed to push
+///
+/// Cloning works over SSH through `git-shell` and over HTTP through the [`crate::http_clone`]
+/// routes, pushing only over SSH.
+fn clone_urls(repo: &Repo, config: &Config, user: Option<&User>) -> serde_json::Value {
+    let http_base = config.http_url_base.as_deref().unwrap_or(&config.site_url);
+    let http = format!("{}/{}", http_base.trim_end_matches('/'), repo.name);
+    let ssh = config.ssh_url_base.as_ref()
+        .map(|base| format!("{}{}", base, repo.name));
+    let push = ssh.as_ref()
+        .filter(|_| repo.can_push(user));
+
+    ctx!{ http, ssh, push }
+}
+
+/// List tree entries sorted by name with directories first
+pub(crate) fn ls_files(tree: &git2::Tree<'_>, refs: &str, path: &Path, repo: &Repo, git_repo: &GitRepo, submodules: &Submodules) -> Vec<File> {
     let mut files = tree.iter()
         .filter_map(|entry| {
             let name = entry.name()?.to_owned();
             let mode = FileMode::from_mode(entry.filemode())?;
             let path = path.join(&name);
-            let href = uri!(tree(Path::new(&repo.name), &refs, &path));
-            Some(File { name, path, href, mode })
+            let href = uri!(tree(Path::new(&repo.name), &refs, &path, _));
+
+            let ok_or_warn = |res: Result<usize>| res.map_err(|err| log::warn!("{:?}", err)).ok();
+            let (size, entries) = match mode {
+                FileMode::File | FileMode::Exe => (ok_or_warn(git_repo.blob_size(entry.id())), None),
+                FileMode::Dir => (None, ok_or_warn(git_repo.tree_len(entry.id()))),
+                FileMode::Symlink | FileMode::Submodule => (None, None),
+            };
+
+            let symlink = match mode {
+                FileMode::Symlink => symlink_info(&entry, &path, refs, repo, git_repo),
+                _ => None,
+            };
+            let submodule = match mode {
+                FileMode::Submodule => Some(submodule_info(&entry, submodules.urls.get(&path), submodules)),
+                _ => None,
+            };
+
+            Some(File { name, path, href, mode, size, entries, symlink, submodule })
         })
     .collect::<Vec<_>>();
 
@@ -116,60 +418,242 @@ fn render_ls_files(tree: git2::Tree<'_>, refs: &str, path: &Path, repo: Repo, gi
         _ => Ordering::Equal,
     });
 
-    let readme = render_readme(&refs, &files, &repo, &git_repo);
+    files
+}
+
+fn symlink_info(entry: &git2::TreeEntry<'_>, path: &Path, refs: &str, repo: &Repo, git_repo: &GitRepo) -> Option<Symlink> {
+    let target = match git_repo.symlink_target(entry.id()) {
+        Ok(target) => target,
+        Err(err) => {
+            log::warn!("{:?}", err);
+            return None;
+        }
+    };
+
+    // relative targets are resolved from the directory containing the link
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let href = resolve_relative(dir, &target)
+        .map(|target| uri!(tree(Path::new(&repo.name), refs, &target, _)));
+
+    Some(Symlink { target, href })
+}
+
+/// Submodules of a revision, read before any other git objects since checking whether the
+/// repositories they point to are served has to be awaited
+#[derive(Default)]
+pub(crate) struct Submodules {
+    /// URLs from `.gitmodules` keyed by the submodule path
+    urls: HashMap<PathBuf, String>,
+    /// Names of the repositories served by this site keyed by the URL pointing to them
+    local: HashMap<String, String>,
+}
+
+impl Submodules {
+    /// Read the URLs from `.gitmodules` of `refs`, none of them are linked until [`resolve`]d
+    ///
+    /// [`resolve`]: Submodules::resolve
+    pub(crate) fn read(refs: &str, git_repo: &GitRepo) -> Submodules {
+        let urls = git_repo.submodule_urls(refs).unwrap_or_else(|err| {
+            log::warn!("{:?}", err);
+            HashMap::new()
+        });
+
+        Submodules { urls, local: HashMap::new() }
+    }
+
+    /// Find the URLs which point to repositories served by this site, submodules pointing
+    /// elsewhere are shown without a link
+    pub(crate) async fn resolve(mut self, repo: &Repo, config: &Config, repo_cache: &RepoCache) -> Submodules {
+        for url in self.urls.values() {
+            let name = match local_repo_name(url, repo, config) {
+                Some(name) => name,
+                None => continue,
+            };
+            match repo_cache.repo(config, &name).await {
+                Ok(Some(_)) => {
+                    self.local.insert(url.clone(), name);
+                }
+                Ok(None) => {}
+                Err(err) => log::warn!("{:?}", err),
+            }
+        }
+        self
+    }
+
+    /// Cache key of the submodule links in the listing of `tree`, empty if it doesn't contain
+    /// submodules
+    ///
+    /// The URLs come from `.gitmodules` of the revision, which can change without changing the
+    /// tree, and the links change as repositories come and go.
+    fn key(&self, tree: &git2::Tree<'_>) -> String {
+        if !tree.iter().any(|entry| entry.kind() == Some(git2::ObjectType::Commit)) {
+            return String::new();
+        }
+
+        let mut urls = self.urls.iter().collect::<Vec<_>>();
+        urls.sort_unstable();
+
+        let mut key = String::new();
+        for (path, url) in urls {
+            let local = self.local.get(url).map_or("", String::as_str);
+            key.push_str(&format!("\0{}\0{}\0{}", path.display(), url, local));
+        }
+        key
+    }
+}
+
+fn submodule_info(entry: &git2::TreeEntry<'_>, url: Option<&String>, submodules: &Submodules) -> Submodule {
+    let commit = entry.id().to_string();
+    let href = url
+        .and_then(|url| submodules.local.get(url))
+        .map(|name| uri!(tree(Path::new(name), &commit, Path::new("/"), _)));
+
+    Submodule {
+        commit,
+        url: url.cloned(),
+        href,
+    }
+}
+
+/// Name of the repository on this site `url` points to, if it's hosted here at all
+///
+/// Relative URLs are resolved against the URL of the superproject the same way git does.
+fn local_repo_name(url: &str, repo: &Repo, config: &Config) -> Option<String> {
+    let name = if url.starts_with("./") || url.starts_with("../") {
+        resolve_relative(Path::new(&repo.name), url)?
+    } else {
+        let path = url.strip_prefix(config.site_url.trim_end_matches('/'))?
+            .strip_prefix('/')?;
+        resolve_relative(Path::new(""), path)?
+    };
+
+    let name = name.to_string_lossy();
+    let name = name.strip_suffix(".git").unwrap_or(&name);
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+/// Join a relative `target` to `dir` and resolve `.` and `..`
+///
+/// Returns `None` for absolute targets and targets which would end up outside of the repository.
+fn resolve_relative(dir: &Path, target: &str) -> Option<PathBuf> {
+    if Path::new(target).is_absolute() {
+        return None;
+    }
+
+    let mut resolved = PathBuf::new();
+    for component in dir.join(target).components() {
+        match component {
+            Component::Normal(component) => resolved.push(component),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            // paths within the repository are relative to its root even with a leading slash
+            Component::CurDir | Component::RootDir => {}
+            Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+fn render_blob(blob: git2::Blob, refs: &str, path: &Path, offset: usize, repo: Repo, nav: Nav, config: &Config, render_cache: &RenderCache) -> Result<View> {
+    let name = path.file_name().unwrap()
+        .to_string_lossy()
+        .to_string();
+    let size = blob.size();
+    let raw_href = uri!(raw(Path::new(&repo.name), refs, path));
+
+    if blob.is_binary() {
+        // pages always start at the beginning of a hexdump line
+        let page_size = config.hexdump_page_size.max(16);
+        let offset = offset.min(size.saturating_sub(1)) / 16 * 16;
+        let end = size.min(offset + page_size);
+
+        let contents = render_cache.get_or_render("hexdump", blob.id(), &format!("{}-{}", offset, end), || {
+            fmt_xxd_hexdump(&blob.content()[offset..end], offset)
+        });
+
+        let page_href = |offset: usize| uri!(tree(Path::new(&repo.name), refs, path, Some(offset)));
+        let hex_href = uri!(crate::hexdump::hex(Path::new(&repo.name), refs, path, Some(offset), _, _));
+        let media = media::detect(&name, blob.content());
+        let pages = ctx!{
+            start = offset,
+            end,
+            prev = (offset > 0).then(|| page_href(offset.saturating_sub(page_size))),
+            next = (end < size).then(|| page_href(end)),
+        };
+
+        return Ok(View::render("file", ctx!{
+            repo,
+            blob = ctx!{ name, contents = *contents, lang = "xxd", size, raw_href, hex_href, pages, media },
+            nav,
+            view = "file",
+        }));
+    }
 
-    Ok(Template::render("tree", ctx!{
+    let lang = blob_lang(&name, &repo);
+    let end = size.min(config.max_text_size);
+    let contents = render_cache.get_or_render("blob", blob.id(), &end.to_string(), || {
+        String::from_utf8_lossy(&blob.content()[..end])
+            .to_string()
+    });
+
+    Ok(View::render("file", ctx!{
         repo,
-        files,
-        readme,
+        blob = ctx!{ name, contents = *contents, lang, size, raw_href, truncated = end < size },
         nav,
-        view = "tree",
+        view = "file",
     }))
 }
 
-fn render_blob(blob: git2::Blob, path: &Path, repo: Repo, nav: Nav) -> Result<Template> {
+/// Summary of a blob which is too large to be shown
+pub(crate) fn render_large_blob(blob: &LargeBlob, refs: &str, path: &Path, repo: Repo, nav: Nav) -> Result<View> {
     let name = path.file_name().unwrap()
         .to_string_lossy()
         .to_string();
+    let raw_href = uri!(raw(Path::new(&repo.name), refs, path));
 
-    let contents;
-    let lang;
+    // the content isn't loaded so the extension is all there is
+    let media = media::detect_by_extension(&name);
 
-    if blob.is_binary() {
-        contents = fmt_xxd_hexdump(blob.content());
-        lang = Some(String::from("xxd"));
-    } else {
-        contents = String::from_utf8_lossy(blob.content())
-            .to_string();
-        lang = repo.lang_override.iter()
-            .find(|(patt, _)| patt.matches(&name))
-            .map(|(_, lang)| lang.clone());
-    }
-
-    Ok(Template::render("file", ctx!{
+    Ok(View::render("file", ctx!{
         repo,
-        blob = ctx!{ name, contents, lang },
+        blob = ctx!{ name, size = blob.size, raw_href, too_large = true, media },
         nav,
         view = "file",
     }))
 }
 
+/// Language override configured for the file name, `None` means the language will be detected
+/// client side
+pub(crate) fn blob_lang(name: &str, repo: &Repo) -> Option<String> {
+    repo.lang_override.iter()
+        .find(|(patt, _)| patt.matches(name))
+        .map(|(_, lang)| lang.clone())
+}
 
-#[derive(Serialize)]
+
+#[derive(Serialize, Deserialize)]
 struct Readme {
     content: String,
     is_html: bool,
 }
 
-fn render_readme(refs: &str, files: &[File], repo: &Repo, git_repo: &GitRepo) -> Option<Readme> {
+fn render_readme(refs: &str, files: &[File], repo: &Repo, git_repo: &GitRepo, config: &Config, render_cache: &RenderCache) -> Option<Arc<Readme>> {
     let path = repo.readme_path.as_ref()?;
     let file = files.iter().find(|file| &file.path == path)?;
 
-    let res = git_repo.find_subtree_object_by_path(&refs, &path)
+    let res = git_repo.find_subtree_object_by_path(&refs, &path, Some(config.max_text_size))
         .with_context(|| format!("finding path {:?} in repo {:?}", &path, &repo.path))
         .transpose()?;
     let blob = match res {
         Ok(Object::Blob(blob)) => blob,
+        Ok(Object::LargeBlob(blob)) => {
+            log::warn!("readme file {:?} is present but is too large ({} bytes)", &path, blob.size);
+            return None;
+        }
         Ok(_) => return None,
         Err(err) => {
             log::warn!("{:?}", err);
@@ -183,9 +667,17 @@ fn render_readme(refs: &str, files: &[File], repo: &Repo, git_repo: &GitRepo) ->
         return None;
     }
 
-    let text_lossy = String::from_utf8_lossy(blob.content());
+    // markdown is rendered by the extension so it's part of the key
+    let is_markdown = file.name.ends_with(".md");
+    let options = if is_markdown { "md" } else { "" };
+    let readme = render_cache.get_or_render("readme", blob.id(), options, || {
+        readme_from_text(&String::from_utf8_lossy(blob.content()), is_markdown)
+    });
+    Some(readme)
+}
 
-    if file.name.ends_with(".md") {
+fn readme_from_text(text_lossy: &str, is_markdown: bool) -> Readme {
+    if is_markdown {
         use pulldown_cmark::{Parser, Options, html};
 
         let options = Options::ENABLE_TABLES |
@@ -194,24 +686,25 @@ fn render_readme(refs: &str, files: &[File], repo: &Repo, git_repo: &GitRepo) ->
                       Options::ENABLE_TASKLISTS |
                       Options::ENABLE_SMART_PUNCTUATION;
 
-        let parser = Parser::new_ext(&text_lossy, options);
+        let parser = Parser::new_ext(text_lossy, options);
 
         let mut html_output = String::new();
         html::push_html(&mut html_output, parser);
 
-        return Some(Readme {
+        return Readme {
             content: html_output,
             is_html: true,
-        });
+        };
     }
 
-    Some(Readme {
+    Readme {
         content: text_lossy.to_string(),
         is_html: false,
-    })
+    }
 }
 
-fn fmt_xxd_hexdump(data: &[u8]) -> String {
+/// Format `data` like `xxd`, `start` is the offset of `data` within the blob
+fn fmt_xxd_hexdump(data: &[u8], start: usize) -> String {
     fn concat(sep: &'static str) -> impl Fn(String, String) -> String {
         move |mut acc, elm| {
             acc.push_str(&elm);
@@ -222,7 +715,7 @@ fn fmt_xxd_hexdump(data: &[u8]) -> String {
 
     data.chunks(16)
         .enumerate()
-        .map(|(offset, chunk)| {
+        .map(|(line, chunk)| {
             let hex = chunk.chunks(2)
                 .map(|word| match word {
                     [a, b] => format!("{:02x}{:02x}", a, b),
@@ -235,7 +728,7 @@ fn fmt_xxd_hexdump(data: &[u8]) -> String {
                 .map(|chr| if chr.is_ascii_graphic() { *chr as char } else { '.' })
                 .collect::<String>();
 
-            format!("{:08x}: {: <39} {}", offset, hex, ascii)
+            format!("{:08x}: {: <39} {}", start + line * 16, hex, ascii)
         })
         .fold(String::with_capacity(data.len() / 16 * 68), concat("\n"))
 }
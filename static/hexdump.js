@@ -0,0 +1,57 @@
+// Highlight the byte range selected in the URL fragment of the hex viewer
+//
+// The fragment is either a single offset or an inclusive range, offsets are decimal or hex with a
+// `0x` prefix, eg. `#0x10-0x1f`. Clicking a byte selects it, shift-click extends the selection.
+
+function parseOffset(value) {
+    const offset = /^0x/i.test(value) ? parseInt(value.slice(2), 16) : parseInt(value, 10);
+    return Number.isNaN(offset) ? null : offset;
+}
+
+function selectedRange() {
+    const [start, end] = decodeURIComponent(location.hash.slice(1)).split('-');
+    const first = parseOffset(start);
+    const last = end === undefined ? first : parseOffset(end);
+    if (first === null || last === null) {
+        return null;
+    }
+    return [Math.min(first, last), Math.max(first, last)];
+}
+
+function highlight() {
+    const range = selectedRange();
+    let first = null;
+
+    for (const byte of document.querySelectorAll('pre.hexdump [data-offset]')) {
+        const offset = Number(byte.dataset.offset);
+        const selected = range !== null && offset >= range[0] && offset <= range[1];
+        byte.classList.toggle('selected', selected);
+        if (selected && first === null) {
+            first = byte;
+        }
+    }
+
+    if (first !== null) {
+        first.scrollIntoView({ block: 'center' });
+    }
+}
+
+document.addEventListener('click', (event) => {
+    const byte = event.target.closest('pre.hexdump [data-offset]');
+    if (byte === null) {
+        return;
+    }
+
+    const offset = Number(byte.dataset.offset);
+    const range = selectedRange();
+    const hex = (offset) => '0x' + offset.toString(16);
+
+    if (event.shiftKey && range !== null) {
+        location.hash = hex(Math.min(range[0], offset)) + '-' + hex(Math.max(range[1], offset));
+    } else {
+        location.hash = hex(offset);
+    }
+});
+
+window.addEventListener('hashchange', highlight);
+highlight();
@@ -0,0 +1,29 @@
+// Highlight and scroll to the line selected by a `#L<n>` URL fragment
+//
+// The line numbers are added by highlightjs-line-numbers after the page loads so the lookup is
+// retried for a moment.
+
+function selectLine(attempts) {
+    const match = /^#L(\d+)$/.exec(location.hash);
+    for (const selected of document.querySelectorAll('.hljs-ln .selected')) {
+        selected.classList.remove('selected');
+    }
+    if (match === null) {
+        return;
+    }
+
+    const number = document.querySelector(`.hljs-ln-numbers[data-line-number="${match[1]}"]`);
+    if (number === null) {
+        if (attempts > 0) {
+            setTimeout(() => selectLine(attempts - 1), 100);
+        }
+        return;
+    }
+
+    const row = number.parentElement;
+    row.classList.add('selected');
+    row.scrollIntoView({ block: 'center' });
+}
+
+window.addEventListener('hashchange', () => selectLine(0));
+window.addEventListener('load', () => selectLine(20));
@@ -0,0 +1,28 @@
+// Filter the rows of the ref switcher while typing into the filter box
+//
+// Rows carry the text they're matched against in `data-ref`, sections left without any visible
+// row are hidden together with their header.
+
+function filterRefs(query) {
+    query = query.trim().toLowerCase();
+    for (const body of document.querySelectorAll('section.refs tbody')) {
+        let visible = 0;
+        for (const row of body.querySelectorAll('tr[data-ref]')) {
+            const matches = row.dataset.ref.toLowerCase().includes(query);
+            row.hidden = !matches;
+            visible += matches ? 1 : 0;
+        }
+        body.hidden = visible === 0;
+        const head = body.previousElementSibling;
+        if (head !== null && head.tagName === 'THEAD') {
+            head.hidden = body.hidden;
+        }
+    }
+}
+
+window.addEventListener('DOMContentLoaded', () => {
+    const input = document.getElementById('ref-filter');
+    if (input !== null) {
+        input.addEventListener('input', () => filterRefs(input.value));
+    }
+});